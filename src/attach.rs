@@ -0,0 +1,59 @@
+//! Persistent shells in tmux or screen on a host, for `qs connect --session`
+//! and `qs sessions`.
+
+use crate::config::Multiplexer;
+use crate::output::SessionRecord;
+use crate::util::shell_join;
+
+/// Session name when the profile sets `session` but no name is given.
+pub const DEFAULT_NAME: &str = "qs";
+
+/// Attaches to the session `name`, creating it if it doesn't exist. Other
+/// clients attached to it are left alone with tmux and detached with screen,
+/// which only allows one.
+pub fn attach_command(multiplexer: Multiplexer, name: &str) -> String {
+    let args = match multiplexer {
+        Multiplexer::Tmux => ["tmux", "new-session", "-A", "-s", name],
+        Multiplexer::Screen => ["screen", "-D", "-R", "-S", name],
+    };
+    shell_join(&args.map(str::to_string))
+}
+
+/// Lists the sessions in a format [`parse_sessions`] reads. Prints nothing
+/// if the program isn't installed or has no sessions.
+pub fn list_command(multiplexer: Multiplexer) -> &'static str {
+    match multiplexer {
+        Multiplexer::Tmux => {
+            "tmux list-sessions -F '#{session_attached} #{session_name}' 2>/dev/null"
+        }
+        Multiplexer::Screen => "screen -ls 2>/dev/null",
+    }
+}
+
+pub fn parse_sessions(multiplexer: Multiplexer, output: &str) -> Vec<SessionRecord> {
+    let session = |name: &str, attached| SessionRecord {
+        name: name.to_string(),
+        multiplexer,
+        attached,
+    };
+
+    match multiplexer {
+        // `<attached clients> <name>`
+        Multiplexer::Tmux => output
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(clients, name)| session(name, clients != "0"))
+            .collect(),
+        // `\t<pid>.<name>\t(<date>)\t(Attached)`, the date only in some versions
+        Multiplexer::Screen => output
+            .lines()
+            .filter(|line| line.starts_with('\t'))
+            .filter_map(|line| {
+                let mut fields = line.split('\t').filter(|field| !field.is_empty());
+                let (_, name) = fields.next()?.split_once('.')?;
+                let attached = fields.any(|field| field == "(Attached)");
+                Some(session(name, attached))
+            })
+            .collect(),
+    }
+}
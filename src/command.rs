@@ -1,9 +1,24 @@
-use clap::Subcommand;
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use crate::session::TransferOptions;
+use crate::util::{parse_env, KeyType};
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize SSH keys
-    Init,
+    Init {
+        #[arg(
+            long = "type",
+            value_enum,
+            default_value = "ed25519",
+            help = "Key type"
+        )]
+        key_type: KeyType,
+        #[arg(long, help = "Key file name in ~/.ssh or a path (default: id_<type>)")]
+        name: Option<String>,
+    },
 
     /// Check required dependencies
     Check,
@@ -17,6 +32,10 @@ pub enum Commands {
         user: String,
         #[arg(long, default_value = "22", help = "SSH port (default: 22)")]
         port: u16,
+        #[arg(long, help = "Jump host: an alias or user@host:port")]
+        jump: Option<String>,
+        #[arg(long, help = "Private key to use for this host (created if missing)")]
+        key: Option<String>,
         #[arg(short, long, help = "Skip SSH key copy")]
         skip_key: bool,
         #[arg(short = 'd', long, help = "Make this host the default")]
@@ -30,6 +49,8 @@ pub enum Commands {
         alias: String,
         #[arg(short = 'y', long = "yes", help = "Skip confirmation prompt")]
         yes: bool,
+        #[arg(long, help = "Remove the qs key from the host's authorized_keys first")]
+        revoke: bool,
     },
 
     /// List all configured hosts
@@ -42,21 +63,166 @@ pub enum Commands {
     Connect {
         #[arg(default_value = "default")]
         alias: String,
+        #[arg(long, value_name = "DIR", help = "Start in this remote directory")]
+        cd: Option<String>,
+        #[arg(
+            long = "env",
+            value_name = "KEY=VALUE",
+            value_parser = parse_env,
+            help = "Set an environment variable"
+        )]
+        env: Vec<(String, String)>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            num_args = 0..=1,
+            default_missing_value = "qs",
+            help = "Attach to a persistent tmux or screen session, created if needed (default name: qs)"
+        )]
+        session: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "session",
+            help = "Plain shell, even if the profile sets a session"
+        )]
+        no_session: bool,
     },
 
-    /// Execute a command on a host
-    Exec {
+    /// List persistent tmux and screen sessions on a host
+    Sessions {
         #[arg(default_value = "default")]
         alias: String,
+    },
+
+    /// Execute a command on a host or group of hosts
+    Exec {
+        #[arg(
+            default_value = "default",
+            help = "Alias, group, or comma-separated list of aliases"
+        )]
+        alias: String,
+        #[arg(
+            long,
+            help = "Pass the command to the remote shell as is, for pipes and redirects"
+        )]
+        raw: bool,
+        #[arg(
+            short,
+            long,
+            help = "Give the command a terminal, for interactive programs (default: if stdin and stdout are one)"
+        )]
+        tty: bool,
+        #[arg(
+            long,
+            conflicts_with = "tty",
+            help = "Never give the command a terminal"
+        )]
+        no_tty: bool,
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Run the command in this remote directory"
+        )]
+        cd: Option<String>,
+        #[arg(
+            long = "env",
+            value_name = "KEY=VALUE",
+            value_parser = parse_env,
+            help = "Set an environment variable for the command"
+        )]
+        env: Vec<(String, String)>,
         #[arg(last = true)]
         cmd: Vec<String>,
     },
 
+    /// Run a local script on one or more hosts
+    Run {
+        #[arg(
+            long,
+            value_name = "PROGRAM",
+            help = "Interpreter to run the script with (default: its #! line, or sh)"
+        )]
+        interpreter: Option<String>,
+        #[arg(
+            long = "env",
+            value_name = "KEY=VALUE",
+            value_parser = parse_env,
+            help = "Set an environment variable for the script"
+        )]
+        env: Vec<(String, String)>,
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "[TARGET] SCRIPT [ARGS]",
+            help = "Alias, group or list of aliases (default: default), the script and its arguments"
+        )]
+        args: Vec<String>,
+    },
+
+    /// Forward ports through a host's master connection
+    Forward {
+        #[arg(default_value = "default")]
+        alias: String,
+        #[arg(help = "Saved forwards to use (default: all saved forwards)")]
+        names: Vec<String>,
+        #[arg(
+            short = 'L',
+            value_name = "[BIND:]PORT:HOST:HOSTPORT",
+            help = "Local forward"
+        )]
+        local: Vec<String>,
+        #[arg(
+            short = 'R',
+            value_name = "[BIND:]PORT:HOST:HOSTPORT",
+            help = "Remote forward"
+        )]
+        remote: Vec<String>,
+        #[arg(
+            short = 'D',
+            value_name = "[BIND:]PORT",
+            help = "Dynamic SOCKS forward"
+        )]
+        dynamic: Vec<String>,
+        #[arg(short, long, help = "Cancel the forwards instead of opening them")]
+        cancel: bool,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Save the given forward under a name"
+        )]
+        save: Option<String>,
+        #[arg(long, value_name = "NAME", help = "Delete a saved forward")]
+        forget: Option<String>,
+        #[arg(short, long, help = "List saved forwards")]
+        list: bool,
+    },
+
+    /// Manage host groups
+    Group {
+        #[command(subcommand)]
+        action: GroupCommands,
+    },
+
+    /// Import hosts from another tool
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+
+    /// Export hosts for other tools
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+
     /// Send files to a host
     Send {
         source: String,
         #[arg(help = "Format: [alias:]destination")]
         dest: String,
+        #[command(flatten)]
+        transfer: TransferArgs,
     },
 
     /// Get files from a host
@@ -64,11 +230,164 @@ pub enum Commands {
         #[arg(help = "Format: [alias:]source")]
         source: String,
         dest: String,
+        #[command(flatten)]
+        transfer: TransferArgs,
+    },
+
+    /// Mirror a local directory to a host with rsync
+    Sync {
+        #[arg(help = "Local directory, its contents are copied")]
+        source: String,
+        #[arg(help = "Format: [alias:]destination")]
+        dest: String,
+        #[arg(long, help = "Delete remote files that don't exist locally")]
+        delete: bool,
+        #[arg(
+            long,
+            value_name = "PATTERN",
+            help = "Skip files matching an rsync pattern"
+        )]
+        exclude: Vec<String>,
+        #[arg(long, value_name = "FILE", help = "Read exclude patterns from a file")]
+        exclude_from: Vec<PathBuf>,
+        #[arg(long, help = "Also copy files that .gitignore files exclude")]
+        no_gitignore: bool,
+        #[arg(short = 'n', long, help = "Show what would change without copying")]
+        dry_run: bool,
+        #[arg(short, long, help = "Compare files by checksum, not size and time")]
+        checksum: bool,
+        #[arg(
+            short,
+            long,
+            conflicts_with = "dry_run",
+            help = "Keep pushing local changes until interrupted"
+        )]
+        watch: bool,
+        #[command(flatten)]
+        transfer: TransferArgs,
     },
 
     /// Show connection status for a host
     Status {
         #[arg(default_value = "default")]
         alias: String,
+        #[arg(short, long, help = "Show every host and socket file")]
+        all: bool,
+        #[arg(long, requires = "all", help = "Remove stale socket files")]
+        clean: bool,
+    },
+
+    /// Close the master connection to a host
+    Close {
+        #[arg(default_value = "default")]
+        alias: String,
+        #[arg(short, long, help = "Close every master connection")]
+        all: bool,
+    },
+
+    /// Manage the SSH keys used by your hosts
+    Keys {
+        #[command(subcommand)]
+        action: KeyCommands,
     },
 }
+
+#[derive(Subcommand)]
+pub enum KeyCommands {
+    /// Replace the key of every host with a freshly generated one
+    Rotate {
+        #[arg(
+            long = "type",
+            value_enum,
+            help = "Key type for the new key (default: same as the old one)"
+        )]
+        key_type: Option<KeyType>,
+        #[arg(short = 'y', long, help = "Skip confirmation")]
+        yes: bool,
+    },
+
+    /// Remove the host's key from its authorized_keys, keeping the profile
+    Revoke {
+        alias: String,
+        #[arg(short = 'y', long, help = "Skip confirmation")]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GroupCommands {
+    /// Create a group, or replace an existing one
+    Add {
+        name: String,
+        #[arg(required = true, value_delimiter = ',', help = "Aliases in the group")]
+        aliases: Vec<String>,
+    },
+
+    /// Remove a group (the hosts themselves are kept)
+    Remove { name: String },
+
+    /// List all groups
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ImportSource {
+    /// Import Host entries from an OpenSSH config file
+    SshConfig {
+        #[arg(help = "Config file to read (default: ~/.ssh/config)")]
+        path: Option<PathBuf>,
+        #[arg(short = 'n', long, help = "Show what would be imported without saving")]
+        dry_run: bool,
+        #[arg(short = 'o', long, help = "Overwrite aliases that already exist")]
+        overwrite: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportTarget {
+    /// Print all hosts as OpenSSH config Host blocks
+    SshConfig {
+        #[arg(
+            short,
+            long,
+            help = "Write a managed file that is kept in sync on add/remove/set-default"
+        )]
+        managed: bool,
+    },
+}
+
+/// Options shared by `send`, `get` and `sync`.
+#[derive(Args)]
+pub struct TransferArgs {
+    #[arg(long, value_name = "KBPS", help = "Limit bandwidth in KiB per second")]
+    pub bwlimit: Option<u32>,
+    #[arg(
+        long,
+        visible_alias = "resume",
+        help = "Keep partly copied files to resume later"
+    )]
+    pub partial: bool,
+    #[arg(long, help = "Copy permissions even if rsync_args turn that off")]
+    pub preserve_perms: bool,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = clap::value_parser!(u32).range(0..=9),
+        help = "rsync compression level, 0 to 9"
+    )]
+    pub compress_level: Option<u32>,
+    #[arg(last = true, help = "Extra rsync flags, after --")]
+    pub rsync_args: Vec<String>,
+}
+
+impl TransferArgs {
+    pub fn options(self) -> TransferOptions {
+        TransferOptions {
+            bwlimit: self.bwlimit,
+            partial: self.partial,
+            preserve_perms: self.preserve_perms,
+            compress_level: self.compress_level,
+            rsync_args: self.rsync_args,
+        }
+    }
+}
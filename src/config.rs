@@ -1,43 +1,55 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::PathBuf,
+};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+use crate::error::Error;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Config {
     pub default: Option<String>,
     pub profiles: HashMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub groups: HashMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "ConnectionSettings::is_empty")]
+    pub settings: ConnectionSettings,
 }
 
 impl Config {
-    pub fn load() -> Result<Self, String> {
+    pub fn load() -> Result<Self, Error> {
         Self::load_from(Self::path())
     }
 
-    pub fn load_from(path: PathBuf) -> Result<Self, String> {
+    pub fn load_from(path: PathBuf) -> Result<Self, Error> {
         if !path.exists() {
             return Ok(Self::default());
         }
 
-        let content =
-            fs::read_to_string(&path).map_err(|e| format!("Failed to read config file: {e}"))?;
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::Config(format!("Failed to read config file: {e}")))?;
 
-        toml::from_str(&content).map_err(|e| format!("Invalid config file format: {e}"))
+        toml::from_str(&content)
+            .map_err(|e| Error::Config(format!("Invalid config file format: {e}")))
     }
 
-    pub fn save(&self) -> Result<(), String> {
+    pub fn save(&self) -> Result<(), Error> {
         self.save_to(Self::path())
     }
 
-    pub fn save_to(&self, path: PathBuf) -> Result<(), String> {
+    pub fn save_to(&self, path: PathBuf) -> Result<(), Error> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create config directory: {e}"))?;
+                .map_err(|e| Error::Config(format!("Failed to create config directory: {e}")))?;
         }
 
-        let content =
-            toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize config: {e}"))?;
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| Error::Config(format!("Failed to serialize config: {e}")))?;
 
-        fs::write(&path, content).map_err(|e| format!("Failed to write config file: {e}"))?;
+        fs::write(&path, content)
+            .map_err(|e| Error::Config(format!("Failed to write config file: {e}")))?;
 
         Ok(())
     }
@@ -50,37 +62,308 @@ impl Config {
             .join("config.toml")
     }
 
-    pub fn get_profile(&self, alias: &str) -> Result<&Profile, String> {
+    pub fn get_profile(&self, alias: &str) -> Result<&Profile, Error> {
         if alias == "default" {
             match &self.default {
-                Some(default_alias) => self
-                    .profiles
-                    .get(default_alias)
-                    .ok_or_else(|| format!("Default alias '{default_alias}' not found")),
+                Some(default_alias) => self.profiles.get(default_alias).ok_or_else(|| {
+                    Error::Alias(format!("Default alias '{default_alias}' not found"))
+                }),
                 None => {
                     if self.profiles.is_empty() {
-                        Err("No host found. Add a host: 'qs add <alias> --host <host> --user <user>'".to_string())
+                        Err(Error::Alias("No host found. Add a host: 'qs add <alias> --host <host> --user <user>'".to_string()))
                     } else {
-                        Err("No default set. Use 'qs set-default <alias>'".to_string())
+                        Err(Error::Alias(
+                            "No default set. Use 'qs set-default <alias>'".to_string(),
+                        ))
                     }
                 }
             }
         } else {
             self.profiles
                 .get(alias)
-                .ok_or_else(|| format!("Alias '{alias}' doesn't exist"))
+                .ok_or_else(|| Error::Alias(format!("Alias '{alias}' doesn't exist")))
+        }
+    }
+
+    /// Resolves an exec target into the list of aliases it refers to.
+    ///
+    /// A target can be a single alias (including `default`), a group name, or a
+    /// comma-separated list of aliases and groups. Duplicates are dropped while
+    /// keeping the first occurrence.
+    pub fn resolve_targets(&self, target: &str) -> Result<Vec<String>, Error> {
+        let mut aliases: Vec<String> = Vec::new();
+
+        for name in target.split(',').map(str::trim) {
+            if name.is_empty() {
+                return Err(Error::Alias(format!("Invalid target '{target}'")));
+            }
+
+            let resolved = match self.groups.get(name) {
+                Some(members) => {
+                    for member in members {
+                        if !self.profiles.contains_key(member) {
+                            return Err(Error::Config(format!(
+                                "Group '{name}' references unknown alias '{member}'"
+                            )));
+                        }
+                    }
+                    members.clone()
+                }
+                None => vec![self.resolve_alias(name)?],
+            };
+
+            for alias in resolved {
+                if !aliases.contains(&alias) {
+                    aliases.push(alias);
+                }
+            }
+        }
+
+        Ok(aliases)
+    }
+
+    /// Resolves the jump chain of a profile into a `-J`/`ProxyJump` value.
+    ///
+    /// A jump can name another alias, which is followed recursively through its
+    /// own `jump`, or be a literal `user@host:port` string that is used as is.
+    /// Hops are ordered from the outermost bastion inwards.
+    pub fn resolve_jump(&self, profile: &Profile) -> Result<Option<String>, Error> {
+        let mut hops = Vec::new();
+        let mut seen: Vec<String> = Vec::new();
+        let mut next = profile.jump.clone();
+
+        while let Some(jump) = next {
+            match self.profiles.get(&jump) {
+                Some(hop) => {
+                    if seen.contains(&jump) {
+                        seen.push(jump);
+                        return Err(Error::Config(format!(
+                            "Jump host cycle detected: {}",
+                            seen.join(" → ")
+                        )));
+                    }
+                    hops.push(hop.jump_spec());
+                    seen.push(jump);
+                    next = hop.jump.clone();
+                }
+                None => {
+                    hops.push(jump);
+                    next = None;
+                }
+            }
+        }
+
+        if hops.is_empty() {
+            return Ok(None);
+        }
+
+        hops.reverse();
+        Ok(Some(hops.join(",")))
+    }
+
+    /// Returns the profile's connection settings, falling back to `[settings]`
+    /// for anything the profile doesn't override.
+    pub fn settings_for(&self, profile: &Profile) -> ConnectionSettings {
+        profile.settings.or(&self.settings)
+    }
+
+    /// Returns the concrete alias name, following `default` to the configured default.
+    pub fn resolve_alias(&self, alias: &str) -> Result<String, Error> {
+        self.get_profile(alias)?;
+
+        if alias == "default" {
+            Ok(self.default.clone().unwrap_or_default())
+        } else {
+            Ok(alias.to_string())
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Profile {
     pub host: String,
     pub user: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Alias or `user@host:port` of the bastion to connect through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jump: Option<String>,
+    /// Private key to authenticate with instead of ssh's defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+    /// Named port forwards brought up by `qs forward <alias>`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub forwards: HashMap<String, Forward>,
+    /// How `send` and `get` copy files. Detected per transfer when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<Transfer>,
+    /// Extra rsync flags for every transfer to this host
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rsync_args: Vec<String>,
+    /// Remote directory that shells and commands start in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    /// Environment variables set for shells and commands
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Keeps `connect` shells alive across disconnects when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<Multiplexer>,
+    #[serde(flatten)]
+    pub settings: ConnectionSettings,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            user: String::new(),
+            port: default_port(),
+            jump: None,
+            identity_file: None,
+            forwards: HashMap::new(),
+            transfer: None,
+            rsync_args: Vec::new(),
+            workdir: None,
+            env: BTreeMap::new(),
+            session: None,
+            settings: ConnectionSettings::default(),
+        }
+    }
+}
+
+impl Profile {
+    /// Formats the profile as a `user@host:port` hop for `ssh -J`.
+    pub fn jump_spec(&self) -> String {
+        format!("{}@{}:{}", self.user, self.host, self.port)
+    }
+}
+
+/// Connection tuning, set globally under `[settings]` or per profile.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ConnectionSettings {
+    /// Reuse a master connection per host (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplex: Option<bool>,
+    /// How long an idle master connection stays open (default: 10m)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_persist: Option<String>,
+    /// Where master connection sockets live (default: ~/.ssh/sockets)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_alive_interval: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_alive_count_max: Option<u32>,
+    /// Seconds to wait for the TCP connection to be established
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u32>,
+}
+
+impl ConnectionSettings {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Returns these settings with unset values taken from `fallback`.
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            multiplex: self.multiplex.or(fallback.multiplex),
+            control_persist: self
+                .control_persist
+                .clone()
+                .or_else(|| fallback.control_persist.clone()),
+            socket_dir: self
+                .socket_dir
+                .clone()
+                .or_else(|| fallback.socket_dir.clone()),
+            server_alive_interval: self
+                .server_alive_interval
+                .or(fallback.server_alive_interval),
+            server_alive_count_max: self
+                .server_alive_count_max
+                .or(fallback.server_alive_count_max),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+        }
+    }
+
+    pub fn multiplex(&self) -> bool {
+        self.multiplex.unwrap_or(true)
+    }
+
+    pub fn control_persist(&self) -> &str {
+        self.control_persist.as_deref().unwrap_or("10m")
+    }
 }
 
 fn default_port() -> u16 {
     22
 }
+
+/// Program used to copy files to and from a host.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    Rsync,
+    Scp,
+    Sftp,
+}
+
+impl Transfer {
+    pub fn program(&self) -> &'static str {
+        match self {
+            Transfer::Rsync => "rsync",
+            Transfer::Scp => "scp",
+            Transfer::Sftp => "sftp",
+        }
+    }
+}
+
+/// Terminal multiplexer that holds persistent shells on a host.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    pub fn program(&self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Screen => "screen",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+/// A port forward in ssh's own `-L`/`-R`/`-D` syntax.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Forward {
+    #[serde(rename = "type")]
+    pub kind: ForwardKind,
+    pub spec: String,
+}
+
+impl Forward {
+    pub fn flag(&self) -> &'static str {
+        match self.kind {
+            ForwardKind::Local => "-L",
+            ForwardKind::Remote => "-R",
+            ForwardKind::Dynamic => "-D",
+        }
+    }
+}
+
+impl fmt::Display for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.flag(), self.spec)
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::util::missing_tools_msg;
+
+/// Errors returned by the qs library.
+///
+/// Each kind maps to a stable process exit code, see [`Error::exit_code`].
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The config file couldn't be read, parsed or written, or its content is
+    /// inconsistent (e.g. a jump host cycle).
+    Config(String),
+    /// Required local tools are missing.
+    Dependency(Vec<String>),
+    /// An alias or group doesn't exist or isn't a valid name.
+    Alias(String),
+    /// A local SSH key couldn't be created, read or moved.
+    Key(String),
+    /// ssh couldn't be started, or couldn't connect or authenticate.
+    Transport(String),
+    /// The command ran on the remote host but exited with a non-zero code.
+    Remote { code: i32, message: String },
+    /// The transfer program ran but failed. `code` is its exit code.
+    Transfer { code: i32 },
+    /// Local files couldn't be watched for changes.
+    Watch(String),
+}
+
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid arguments, same as clap uses for parse errors.
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONFIG: i32 = 3;
+pub const EXIT_DEPENDENCY: i32 = 4;
+pub const EXIT_ALIAS: i32 = 5;
+pub const EXIT_KEY: i32 = 6;
+/// Same code ssh itself uses when the connection fails.
+pub const EXIT_TRANSPORT: i32 = 255;
+
+impl Error {
+    /// The code `qs` exits with for this error. Remote and transfer failures
+    /// exit with the remote command's or transfer program's own code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => EXIT_CONFIG,
+            Error::Dependency(_) => EXIT_DEPENDENCY,
+            Error::Alias(_) => EXIT_ALIAS,
+            Error::Key(_) => EXIT_KEY,
+            Error::Transport(_) => EXIT_TRANSPORT,
+            Error::Watch(_) => EXIT_FAILURE,
+            Error::Remote { code, .. } | Error::Transfer { code } => *code,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(msg)
+            | Error::Alias(msg)
+            | Error::Key(msg)
+            | Error::Transport(msg)
+            | Error::Watch(msg) => f.write_str(msg),
+            Error::Dependency(missing) => f.write_str(&missing_tools_msg(
+                missing.iter().map(String::as_str).collect(),
+            )),
+            Error::Remote { code, message } if message.is_empty() => {
+                write!(f, "Remote command exited with code {code}")
+            }
+            Error::Remote { message, .. } => f.write_str(message),
+            Error::Transfer { code } => {
+                write!(f, "Transfer failed with exit code {code}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,330 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::config::{Config, Profile};
+use crate::error::Error;
+use crate::transport::Transport;
+use crate::util::{
+    generate_key, public_key_path, setup_multiplex, shell_quote, ssh_args, ssh_target, KeyType,
+};
+
+/// How a rotation went on a single host.
+#[derive(Debug, PartialEq)]
+pub enum HostOutcome {
+    /// The new key works and the old one was removed.
+    Rotated,
+    /// The new key works, but the old line could not be removed.
+    OldKeyKept(String),
+    Failed(String),
+}
+
+pub struct RotationReport {
+    pub key: PathBuf,
+    pub hosts: Vec<(String, HostOutcome)>,
+    /// Whether the old key was moved aside locally.
+    pub retired: bool,
+}
+
+pub fn read_public_key(key_path: &Path) -> Result<String, Error> {
+    let path = public_key_path(key_path);
+    fs::read_to_string(&path)
+        .map(|key| key.trim().to_string())
+        .map_err(|e| Error::Key(format!("Failed to read {}: {e}", path.display())))
+}
+
+/// The base64 part of a public key line, which identifies the key regardless
+/// of its comment or options.
+pub fn key_blob(pubkey: &str) -> Option<&str> {
+    pubkey.split_whitespace().nth(1)
+}
+
+/// Works out the key type from a public key line such as `ssh-ed25519 AAAA...`.
+pub fn key_type_of(pubkey: &str) -> Option<KeyType> {
+    match pubkey.split_whitespace().next()? {
+        "ssh-ed25519" => Some(KeyType::Ed25519),
+        "ssh-rsa" => Some(KeyType::Rsa),
+        t if t.starts_with("ecdsa-sha2-") => Some(KeyType::Ecdsa),
+        _ => None,
+    }
+}
+
+/// Default sshd config, read remotely to find `AuthorizedKeysFile`.
+pub const SSHD_CONFIG: &str = "/etc/ssh/sshd_config";
+
+// Sets $f to the first AuthorizedKeysFile of the sshd config in $1, following
+// symlinks so the real file is replaced
+const LOCATE_AUTHORIZED_KEYS: &str = r#"PATH=$PATH:/usr/sbin:/sbin;
+akf=$( (sshd -T -f "$1" 2>/dev/null || cat "$1" 2>/dev/null) | awk 'tolower($1) == "authorizedkeysfile" { print $2; exit }' | sed -e "s|%h|$HOME|g" -e "s|%u|$(id -un)|g" -e 's|%%|%|g');
+case $akf in ""|none) f=$HOME/.ssh/authorized_keys ;; /*) f=$akf ;; *) f=$HOME/$akf ;; esac;
+if [ -L "$f" ]; then f=$(readlink -f "$f"); fi;
+tmp="$f.qs-tmp.$$";
+umask 077;"#;
+
+// Moves $tmp over $f in one step, keeping the SELinux context of the old file
+const REPLACE_AUTHORIZED_KEYS: &str = r#"if [ -f "$f" ]; then chcon --reference="$f" "$tmp" 2>/dev/null; fi;
+mv -f "$tmp" "$f" || { rm -f "$tmp"; exit 1; };"#;
+
+const INSTALL_KEY: &str = r#"if [ -f "$f" ] && grep -qF -e "$2" "$f"; then echo present; exit 0; fi;
+if [ -f "$f" ]; then new=; else new=1; fi;
+mkdir -p "$(dirname "$f")" || exit 1;
+{ if [ -s "$f" ]; then cat "$f"; if [ -n "$(tail -c 1 "$f")" ]; then echo; fi; fi; printf '%s\n' "$3"; } > "$tmp" || { rm -f "$tmp"; exit 1; };
+{replace}
+if [ -n "$new" ] && command -v restorecon >/dev/null 2>&1; then restorecon "$(dirname "$f")" "$f" 2>/dev/null; fi;
+echo added"#;
+
+const REMOVE_KEY: &str = r#"if [ ! -f "$f" ] || ! grep -qF -e "$2" "$f"; then echo absent; exit 0; fi;
+grep -vF -e "$2" "$f" > "$tmp";
+if ! grep -Evq '^[[:space:]]*(#|$)' "$tmp"; then rm -f "$tmp"; echo 'Refusing to remove the only key in authorized_keys' >&2; exit 3; fi;
+{replace}
+echo removed"#;
+
+/// Wraps `body` so it runs under `sh` whatever the remote login shell is,
+/// with `args` as its positional parameters.
+fn sh_script(body: &str, args: &[&str]) -> String {
+    let body = format!("{LOCATE_AUTHORIZED_KEYS}\n{body}")
+        .replace("{replace}", REPLACE_AUTHORIZED_KEYS)
+        .replace('\n', " ");
+    let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+    format!("sh -c {} qs {}", shell_quote(&body), args.join(" "))
+}
+
+/// Remote script that adds `pubkey` to the `authorized_keys` file named in
+/// `sshd_config` unless it's already there. The file is rewritten through a
+/// temporary copy, so it's never left half written. Prints `added` or
+/// `present`.
+pub fn install_key_script(pubkey: &str, sshd_config: &str) -> String {
+    let blob = key_blob(pubkey).unwrap_or(pubkey);
+    sh_script(INSTALL_KEY, &[sshd_config, blob, pubkey])
+}
+
+/// Remote script that drops every `authorized_keys` line containing
+/// `pubkey` and prints `removed`, or `absent` if there was no such line. It
+/// exits with 3 and leaves the file alone rather than leave it without keys.
+pub fn remove_key_script(pubkey: &str, sshd_config: &str) -> String {
+    let blob = key_blob(pubkey).unwrap_or(pubkey);
+    sh_script(REMOVE_KEY, &[sshd_config, blob])
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InstallOutcome {
+    Added,
+    AlreadyInstalled,
+}
+
+/// Installs `pubkey` on the host `cmd` connects to.
+pub fn install_key(
+    transport: &dyn Transport,
+    cmd: Command,
+    pubkey: &str,
+) -> Result<InstallOutcome, Error> {
+    install_outcome(&run_remote(
+        transport,
+        cmd,
+        &install_key_script(pubkey, SSHD_CONFIG),
+    )?)
+}
+
+/// Reads what [`install_key_script`] printed.
+pub fn install_outcome(output: &str) -> Result<InstallOutcome, Error> {
+    match output {
+        "added" => Ok(InstallOutcome::Added),
+        "present" => Ok(InstallOutcome::AlreadyInstalled),
+        other => Err(Error::Transport(format!(
+            "Unexpected output from the remote host: {other}"
+        ))),
+    }
+}
+
+/// ssh invocation that reuses (or starts) the master connection for `profile`.
+/// With `batch`, ssh fails instead of prompting for a password.
+pub fn master_command(config: &Config, profile: &Profile, batch: bool) -> Result<Command, Error> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(setup_multiplex(&config.settings_for(profile)));
+    if batch {
+        cmd.arg("-o").arg("BatchMode=yes");
+    }
+    cmd.args(ssh_args(config, profile)?);
+    cmd.arg(ssh_target(profile));
+    Ok(cmd)
+}
+
+/// Fresh ssh invocation that authenticates with `key` only. The master
+/// connection is bypassed, otherwise the login would prove nothing.
+pub fn key_command(config: &Config, profile: &Profile, key: &Path) -> Result<Command, Error> {
+    let profile = Profile {
+        identity_file: Some(key.display().to_string()),
+        ..profile.clone()
+    };
+
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-o", "ControlMaster=no", "-o", "ControlPath=none"]);
+    cmd.arg("-o").arg("BatchMode=yes");
+    cmd.args(ssh_args(config, &profile)?);
+    cmd.arg(ssh_target(&profile));
+    Ok(cmd)
+}
+
+/// Runs `script` remotely, with the result read by [`remote_result`].
+fn run_remote(transport: &dyn Transport, mut cmd: Command, script: &str) -> Result<String, Error> {
+    cmd.arg(script).stdin(Stdio::null()).stderr(Stdio::piped());
+    let output = transport
+        .output(&mut cmd)
+        .map_err(|e| Error::Transport(format!("Failed to start ssh: {e}")))?;
+
+    remote_result(&output)
+}
+
+/// Interprets the output of a remote script run: its trimmed stdout, or the
+/// last line ssh or the script wrote to stderr.
+pub fn remote_result(output: &Output) -> Result<String, Error> {
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    Err(match output.status.code() {
+        Some(255) if stderr.contains("Permission denied") => {
+            Error::Transport("Authentication failed".to_string())
+        }
+        Some(255) | None => Error::Transport(message),
+        Some(code) => Error::Remote { code, message },
+    })
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Replaces `key` on every host in `aliases`.
+///
+/// The new key is generated next to the old one as `<key>.new` and reused if
+/// a previous run left it behind. Only when every host accepts it is the old
+/// public key removed remotely and the old key pair renamed to `<key>.old`;
+/// otherwise nothing is retired and the command can simply be run again.
+pub fn rotate_key(
+    config: &Config,
+    transport: &dyn Transport,
+    key: &Path,
+    aliases: &[String],
+    key_type: Option<KeyType>,
+) -> Result<RotationReport, Error> {
+    let old_pub = read_public_key(key)?;
+    let key_type = key_type
+        .or_else(|| key_type_of(&old_pub))
+        .unwrap_or(KeyType::Ed25519);
+
+    let new_key = with_suffix(key, ".new");
+    if !new_key.exists() {
+        generate_key(&new_key, key_type)?;
+    }
+    let new_pub = read_public_key(&new_key)?;
+
+    let mut hosts = Vec::new();
+    for alias in aliases {
+        let profile = config.get_profile(alias)?;
+        let outcome = master_command(config, profile, true)
+            .and_then(|cmd| install_key(transport, cmd, &new_pub).map(|_| ()))
+            .map_err(|e| format!("install failed: {e}"))
+            .and_then(|_| {
+                key_command(config, profile, &new_key)
+                    .and_then(|cmd| run_remote(transport, cmd, "true").map(|_| ()))
+                    .map_err(|e| format!("login with the new key failed: {e}"))
+            });
+
+        hosts.push((
+            alias.clone(),
+            match outcome {
+                Ok(_) => HostOutcome::Rotated,
+                Err(e) => HostOutcome::Failed(e),
+            },
+        ));
+    }
+
+    let retired = hosts.iter().all(|(_, o)| *o == HostOutcome::Rotated);
+    if retired {
+        for (alias, outcome) in hosts.iter_mut() {
+            let profile = config.get_profile(alias)?;
+            let removed = key_command(config, profile, &new_key).and_then(|cmd| {
+                run_remote(transport, cmd, &remove_key_script(&old_pub, SSHD_CONFIG))
+            });
+            if let Err(e) = removed {
+                *outcome = HostOutcome::OldKeyKept(e.to_string());
+            }
+        }
+
+        retire_key(key, &new_key)?;
+    }
+
+    Ok(RotationReport {
+        key: key.to_path_buf(),
+        hosts,
+        retired,
+    })
+}
+
+/// Whether `key` on its own is enough to log in to `profile`.
+pub fn key_logs_in(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+    key: &Path,
+) -> bool {
+    key_command(config, profile, key)
+        .and_then(|cmd| run_remote(transport, cmd, "true"))
+        .is_ok()
+}
+
+/// Removes the public half of `key` from the remote `authorized_keys` of
+/// `profile`. Returns false if it wasn't installed there.
+pub fn revoke_key(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+    key: &Path,
+) -> Result<bool, Error> {
+    let pubkey = read_public_key(key)?;
+    let cmd = master_command(config, profile, false)?;
+
+    match run_remote(transport, cmd, &remove_key_script(&pubkey, SSHD_CONFIG))?.as_str() {
+        "removed" => Ok(true),
+        "absent" => Ok(false),
+        other => Err(Error::Transport(format!(
+            "Unexpected output from the remote host: {other}"
+        ))),
+    }
+}
+
+/// Moves `key` to `<key>.old` and `new_key` into its place, public halves
+/// included.
+fn retire_key(key: &Path, new_key: &Path) -> Result<(), Error> {
+    let old_key = with_suffix(key, ".old");
+    let moves = [
+        (key.to_path_buf(), old_key.clone()),
+        (public_key_path(key), public_key_path(&old_key)),
+        (new_key.to_path_buf(), key.to_path_buf()),
+        (public_key_path(new_key), public_key_path(key)),
+    ];
+
+    for (from, to) in moves {
+        fs::rename(&from, &to).map_err(|e| {
+            Error::Key(format!(
+                "Failed to move {} to {}: {e}",
+                from.display(),
+                to.display()
+            ))
+        })?;
+    }
+
+    Ok(())
+}
@@ -1,3 +1,14 @@
+pub mod attach;
 pub mod command;
 pub mod config;
+pub mod error;
+pub mod keys;
+#[cfg(feature = "native-ssh")]
+pub mod native;
+pub mod output;
+pub mod script;
+pub mod session;
+pub mod ssh_config;
+pub mod transport;
 pub mod util;
+pub mod watch;
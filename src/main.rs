@@ -1,19 +1,46 @@
 use clap::Parser;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
 
+mod attach;
 mod command;
 mod config;
+mod error;
+mod keys;
+#[cfg(feature = "native-ssh")]
+mod native;
+mod output;
+mod script;
+// Parts of the session API are only used by library callers
+#[allow(dead_code)]
+mod session;
+mod ssh_config;
+mod transport;
 mod util;
+mod watch;
 
 use config::Config;
 
-use crate::command::Commands;
-use crate::config::Profile;
+use crate::command::{Commands, ExportTarget, GroupCommands, ImportSource, KeyCommands};
+use crate::config::{Forward, ForwardKind, Profile, Transfer};
+use crate::error::{Error, EXIT_ALIAS, EXIT_FAILURE, EXIT_TRANSPORT, EXIT_USAGE};
+use crate::keys::{key_logs_in, revoke_key, rotate_key, HostOutcome};
+use crate::output::{render, HostRecord, OutputFormat, SocketRecord, StatusRecord, StatusReport};
+use crate::session::{MasterStatus, Session, SyncOptions};
+use crate::ssh_config::{
+    parse_ssh_config, plan_import, render_ssh_config, sync_managed_ssh_config,
+    write_managed_ssh_config, ImportAction,
+};
+use crate::transport::{Process, Transport};
 use crate::util::{
-    check_dependencies, copy_ssh_key_manual, ensure_ssh_key, remove_alias, setup_multiplex,
-    ssh_target, validate_alias,
+    add_group, capture_on_host, capture_on_hosts, check_command, check_dependencies, close_socket,
+    control_path, copy_ssh_key_manual, ensure_key, exec_on_hosts, forward_port, key_path,
+    master_running, profile_key_path, remove_alias, remove_group, setup_multiplex, shell_join,
+    socket_dirs, socket_files, socket_pid, split_remote, ssh_args, ssh_target, start_master,
+    validate_alias,
 };
 
 #[derive(Parser)]
@@ -23,6 +50,15 @@ use crate::util::{
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        help = "Output format for list, status and exec"
+    )]
+    output: OutputFormat,
 }
 
 fn main() {
@@ -30,36 +66,53 @@ fn main() {
     let mut config = Config::load().unwrap_or_else(|err| {
         eprintln!("Error loading config: {err}");
         eprintln!("Please fix the config file or remove it to start fresh.");
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     });
 
+    let format = cli.output;
+
     match cli.command {
         Commands::Check => {
             println!("Checking dependencies...\n");
             match check_dependencies() {
                 Ok(_) => {
                     println!("✓ All required tools are installed");
-                    println!("✓ ssh");
-                    println!("✓ ssh-keygen");
-                    println!("✓ rsync");
+                    for tool in ["ssh", "ssh-keygen", "rsync"] {
+                        if check_command(tool) {
+                            println!("✓ {tool}");
+                        } else if tool == "ssh-keygen" {
+                            println!("- {tool} not found, keys must be created elsewhere");
+                        } else if tool == "rsync" {
+                            println!("- {tool} not found, files are copied with scp or sftp");
+                        } else {
+                            println!("- {tool} not found, using the built-in SSH client");
+                        }
+                    }
                 }
                 Err(msg) => {
                     eprintln!("{msg}");
-                    std::process::exit(1);
+                    std::process::exit(msg.exit_code());
                 }
             }
         }
 
-        Commands::Init => {
+        Commands::Init { key_type, name } => {
             if let Err(msg) = check_dependencies() {
                 eprintln!("{msg}");
                 eprintln!("Run 'qs check' after installing missing tools");
-                std::process::exit(1);
+                std::process::exit(msg.exit_code());
             }
 
-            let key_path = ensure_ssh_key();
-            println!("✓ SSH key ready at {}", key_path.display());
-            println!("✓ Public key at {}.pub", key_path.display());
+            let name = name.unwrap_or_else(|| key_type.default_file_name().to_string());
+            let path = match ensure_key(&key_path(&name), key_type) {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(e.exit_code());
+                }
+            };
+            println!("✓ SSH key ready at {}", path.display());
+            println!("✓ Public key at {}.pub", path.display());
             println!("\nNext: Add a host with 'qs add <alias> --host <host> --user <user>'");
         }
 
@@ -68,18 +121,26 @@ fn main() {
             host,
             user,
             port,
+            jump,
+            key,
             skip_key,
             is_default,
             overwrite,
         } => {
             if let Err(msg) = check_dependencies() {
                 eprintln!("{msg}");
-                std::process::exit(1);
+                std::process::exit(msg.exit_code());
             }
 
             if let Err(error) = validate_alias(&alias) {
                 eprintln!("Error: {error}");
-                std::process::exit(1);
+                std::process::exit(error.exit_code());
+            }
+
+            // Groups win when resolving targets, the alias could never be used
+            if config.groups.contains_key(&alias) {
+                eprintln!("Error: '{alias}' is already used as a group");
+                std::process::exit(EXIT_ALIAS);
             }
 
             if let Some(existing_host) = config.profiles.get(&alias) {
@@ -88,15 +149,33 @@ fn main() {
                     eprintln!("  Host: {}", existing_host.host);
                     eprintln!("  User: {}", existing_host.user);
                     eprintln!("  Port: {}", existing_host.port);
+                    if let Some(jump) = &existing_host.jump {
+                        eprintln!("  Jump: {jump}");
+                    }
+                    if let Some(identity_file) = &existing_host.identity_file {
+                        eprintln!("  Key: {identity_file}");
+                    }
                     eprintln!("\nUse --overwrite to replace the existing alias");
-                    std::process::exit(1);
+                    std::process::exit(EXIT_ALIAS);
                 }
             }
 
-            let profile = Profile { host, user, port };
+            let profile = Profile {
+                host,
+                user,
+                port,
+                jump,
+                identity_file: key.map(|k| key_path(&k).display().to_string()),
+                ..Default::default()
+            };
+
+            if let Err(error) = config.resolve_jump(&profile) {
+                eprintln!("Error: {error}");
+                std::process::exit(error.exit_code());
+            }
 
             if !skip_key {
-                copy_ssh_key_manual(&profile);
+                copy_ssh_key_manual(&config, &Process, &profile);
             }
 
             config.profiles.insert(alias.clone(), profile);
@@ -107,26 +186,29 @@ fn main() {
             }
             if let Err(e) = config.save() {
                 eprintln!("Error saving config: {e}");
-                std::process::exit(1);
+                std::process::exit(e.exit_code());
             }
+            sync_ssh_config(&config);
             println!("✓ Added alias: {alias}");
         }
 
-        Commands::Remove { alias, yes } => {
-            if !yes {
-                print!("Are you sure you want to remove '{alias}'? [y/N]: ");
-                io::stdout().flush().unwrap_or(());
-
-                let mut input = String::new();
-                io::stdin().read_line(&mut input).unwrap_or_else(|_| {
-                    eprintln!("Failed to read input");
-                    std::process::exit(1);
-                });
+        Commands::Remove { alias, yes, revoke } => {
+            if !yes && !confirm(&format!("Are you sure you want to remove '{alias}'?")) {
+                println!("Removal cancelled");
+                return;
+            }
 
-                let input = input.trim().to_lowercase();
-                if input != "y" && input != "yes" {
-                    println!("Removal cancelled");
-                    return;
+            if revoke && config.profiles.contains_key(&alias) {
+                match revoke_host_key(&config, &alias, yes) {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(err) => {
+                        eprintln!("✗ Failed to revoke key on {alias}: {err}");
+                        eprintln!(
+                            "'{alias}' was not removed. Run without --revoke to remove it anyway."
+                        );
+                        std::process::exit(err.exit_code());
+                    }
                 }
             }
 
@@ -138,8 +220,9 @@ fn main() {
 
                     if let Err(e) = config.save() {
                         eprintln!("Error saving config: {e}");
-                        std::process::exit(1);
+                        std::process::exit(e.exit_code());
                     }
+                    sync_ssh_config(&config);
                 }
                 Err(error) => {
                     eprintln!("{error}");
@@ -147,12 +230,27 @@ fn main() {
                     for alias_name in config.profiles.keys() {
                         eprintln!("  - {alias_name}");
                     }
-                    std::process::exit(1);
+                    std::process::exit(error.exit_code());
                 }
             }
         }
 
         Commands::List => {
+            if let Some(data) = format.data() {
+                let mut hosts: Vec<_> = config
+                    .profiles
+                    .iter()
+                    .map(|(alias, profile)| HostRecord {
+                        alias: alias.clone(),
+                        default: Some(alias) == config.default.as_ref(),
+                        profile: profile.clone(),
+                    })
+                    .collect();
+                hosts.sort_by(|a, b| a.alias.cmp(&b.alias));
+                print!("{}", render(&hosts, data));
+                return;
+            }
+
             if config.profiles.is_empty() {
                 println!(
                     "No hosts configured. Use 'qs add <alias> --host <host> --user <user>' to add one."
@@ -172,39 +270,199 @@ fn main() {
                 } else {
                     String::new()
                 };
+                let jump_info = match &profile.jump {
+                    Some(jump) => format!(" (via {jump})"),
+                    None => String::new(),
+                };
                 println!(
-                    "  {}{}: {}@{}{}",
-                    alias, default, profile.user, profile.host, port_info
+                    "  {}{}: {}@{}{}{}",
+                    alias, default, profile.user, profile.host, port_info, jump_info
                 );
             }
         }
 
-        Commands::Connect { alias } => {
-            let profile = config.get_profile(&alias).unwrap_or_else(|err| {
+        Commands::Connect {
+            alias,
+            cd,
+            env,
+            session: session_name,
+            no_session,
+        } => {
+            let alias = config.resolve_alias(&alias).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+            override_workdir(&mut config, std::slice::from_ref(&alias), cd, env);
+
+            let session = Session::from_config(&config, &alias).unwrap_or_else(|err| {
                 eprintln!("{err}");
-                std::process::exit(1);
+                std::process::exit(err.exit_code());
             });
+            let session_name = match session_name {
+                Some(name) => Some(name),
+                None if no_session => None,
+                None => session
+                    .profile()
+                    .session
+                    .map(|_| attach::DEFAULT_NAME.to_string()),
+            };
 
-            let mut cmd = Command::new("ssh");
-            cmd.args(setup_multiplex());
-            if profile.port != 22 {
-                cmd.arg("-p").arg(profile.port.to_string());
+            let status = match session_name {
+                Some(name) => session.attach(&name),
+                None => session.run(None),
             }
-            cmd.arg(ssh_target(profile));
-            cmd.status().ok();
+            .unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+            exit_with_status(Ok(status), "ssh");
         }
 
-        Commands::Send { source, dest } => {
-            let (alias_name, remote_path) = if dest.contains(':') {
-                let parts: Vec<_> = dest.splitn(2, ':').collect();
-                (parts[0], parts[1].to_string())
-            } else {
-                ("default", dest.clone())
-            };
+        Commands::Sessions { alias } => {
+            let session = Session::from_config(&config, &alias).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+            let sessions = session.sessions().unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+
+            if let Some(data) = format.data() {
+                print!("{}", render(&sessions, data));
+                return;
+            }
+
+            if sessions.is_empty() {
+                println!("No sessions on {}", session.alias());
+                return;
+            }
+
+            println!("Sessions on {}:\n", session.alias());
+            for record in &sessions {
+                let attached = if record.attached { " (attached)" } else { "" };
+                println!(
+                    "  {} [{}]{attached}",
+                    record.name,
+                    record.multiplexer.program()
+                );
+            }
+            println!(
+                "\nAttach with: qs connect {} --session <name>",
+                session.alias()
+            );
+        }
+
+        Commands::Import { source } => match source {
+            ImportSource::SshConfig {
+                path,
+                dry_run,
+                overwrite,
+            } => {
+                let path = path.unwrap_or_else(ssh_config::default_path);
+                let hosts = parse_ssh_config(&path).unwrap_or_else(|err| {
+                    eprintln!("Error: {err}");
+                    std::process::exit(err.exit_code());
+                });
+
+                if hosts.is_empty() {
+                    println!("No hosts found in {}", path.display());
+                    return;
+                }
+
+                let dry_run_info = if dry_run { " (dry run)" } else { "" };
+                println!("Importing from {}{dry_run_info}\n", path.display());
+
+                let (mut imported, mut conflicts) = (0, 0);
+                for entry in plan_import(&config, hosts, overwrite) {
+                    let alias = entry.alias;
+                    let summary = entry
+                        .profile
+                        .as_ref()
+                        .map(|p| format!("{}@{}:{}", p.user, p.host, p.port))
+                        .unwrap_or_default();
+
+                    match &entry.action {
+                        ImportAction::Add => println!("  + {alias}: {summary}"),
+                        ImportAction::Overwrite => println!("  ~ {alias}: {summary} (overwrite)"),
+                        ImportAction::Unchanged => println!("  = {alias}: already up to date"),
+                        ImportAction::Conflict => {
+                            conflicts += 1;
+                            println!("  ! {alias}: alias already exists with different settings");
+                        }
+                        ImportAction::Invalid(reason) => println!("  ✗ {alias}: {reason}"),
+                    }
+
+                    if matches!(entry.action, ImportAction::Add | ImportAction::Overwrite) {
+                        imported += 1;
+                        if let Some(profile) = entry.profile {
+                            config.profiles.insert(alias.clone(), profile);
+                            if config.default.is_none() {
+                                config.default = Some(alias);
+                            }
+                        }
+                    }
+                }
+
+                println!();
+                if conflicts > 0 {
+                    println!("{conflicts} conflicting alias(es) skipped. Use --overwrite to replace them.");
+                }
+
+                if dry_run {
+                    println!("Dry run: {imported} host(s) would be imported");
+                    return;
+                }
+
+                if imported > 0 {
+                    if let Err(e) = config.save() {
+                        eprintln!("Error saving config: {e}");
+                        std::process::exit(e.exit_code());
+                    }
+                    sync_ssh_config(&config);
+                }
+                println!("✓ Imported {imported} host(s)");
+            }
+        },
+
+        Commands::Export { target } => match target {
+            ExportTarget::SshConfig { managed } => {
+                if !managed {
+                    match render_ssh_config(&config) {
+                        Ok(content) => print!("{content}"),
+                        Err(e) => {
+                            eprintln!("Error: {e}");
+                            std::process::exit(e.exit_code());
+                        }
+                    }
+                    return;
+                }
+
+                match write_managed_ssh_config(&config) {
+                    Ok(path) => {
+                        println!("✓ Wrote {}", path.display());
+                        println!("  It is updated on every add, remove and set-default.");
+                        println!("\nAdd this line at the top of ~/.ssh/config to use it:");
+                        println!("  Include {}", path.display());
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {e}");
+                        std::process::exit(e.exit_code());
+                    }
+                }
+            }
+        },
 
-            let profile = config.get_profile(alias_name).unwrap_or_else(|err| {
+        Commands::Send {
+            source,
+            dest,
+            transfer,
+        } => {
+            let (alias_name, remote_path) = split_remote(&dest);
+
+            let session = Session::from_config(&config, alias_name).unwrap_or_else(|err| {
                 eprintln!("{err}");
-                std::process::exit(1);
+                std::process::exit(err.exit_code());
             });
 
             let absolute_source = std::env::current_dir()
@@ -214,41 +472,26 @@ fn main() {
             if !absolute_source.exists() {
                 eprintln!("Error: Source file '{source}' does not exist");
                 eprintln!("Looking for: {}", absolute_source.display());
-                std::process::exit(1);
+                std::process::exit(EXIT_FAILURE);
             }
 
             println!("Sending {source} → {alias_name}:{remote_path}");
 
-            let mut cmd = Command::new("rsync");
-            cmd.arg("-az");
-            cmd.arg("--progress");
-            cmd.arg("-e");
-            let ssh_opts = if profile.port != 22 {
-                format!("ssh -p {} {}", profile.port, setup_multiplex().join(" "))
-            } else {
-                format!("ssh {}", setup_multiplex().join(" "))
-            };
-            cmd.arg(ssh_opts);
-            cmd.arg(absolute_source.to_string_lossy().to_string());
-            cmd.arg(format!("{}:{}", ssh_target(profile), remote_path));
-
-            if !cmd.status().map(|s| s.success()).unwrap_or(false) {
-                eprintln!("\n✗ Transfer failed");
-                std::process::exit(1);
-            }
+            let session = settle_transfer_method(session.options(transfer.options()));
+            let result = session.progress(true).send(&absolute_source, remote_path);
+            exit_on_transfer_error(result);
         }
 
-        Commands::Get { source, dest } => {
-            let (alias_name, remote_path) = if source.contains(':') {
-                let parts: Vec<_> = source.splitn(2, ':').collect();
-                (parts[0], parts[1].to_string())
-            } else {
-                ("default", source.clone())
-            };
+        Commands::Get {
+            source,
+            dest,
+            transfer,
+        } => {
+            let (alias_name, remote_path) = split_remote(&source);
 
-            let profile = config.get_profile(alias_name).unwrap_or_else(|err| {
+            let session = Session::from_config(&config, alias_name).unwrap_or_else(|err| {
                 eprintln!("{err}");
-                std::process::exit(1);
+                std::process::exit(err.exit_code());
             });
 
             let absolute_dest = std::env::current_dir()
@@ -257,81 +500,603 @@ fn main() {
 
             println!("Getting {alias_name}:{remote_path} → {dest}");
 
-            let mut cmd = Command::new("rsync");
-            cmd.arg("-az");
-            cmd.arg("--progress");
-            cmd.arg("-e");
-            let ssh_opts = if profile.port != 22 {
-                format!("ssh -p {} {}", profile.port, setup_multiplex().join(" "))
-            } else {
-                format!("ssh {}", setup_multiplex().join(" "))
+            let session = settle_transfer_method(session.options(transfer.options()));
+            let result = session.progress(true).get(remote_path, &absolute_dest);
+            exit_on_transfer_error(result);
+        }
+
+        Commands::Sync {
+            source,
+            dest,
+            delete,
+            exclude,
+            exclude_from,
+            no_gitignore,
+            dry_run,
+            checksum,
+            watch,
+            transfer,
+        } => {
+            let (alias_name, remote_path) = split_remote(&dest);
+
+            let session = Session::from_config(&config, alias_name).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+
+            let absolute_source = std::env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from("."))
+                .join(&source);
+
+            if !absolute_source.is_dir() {
+                eprintln!("Error: '{source}' is not a directory");
+                std::process::exit(EXIT_FAILURE);
+            }
+
+            let options = SyncOptions {
+                delete,
+                exclude,
+                exclude_from,
+                gitignore: !no_gitignore,
+                dry_run,
+                checksum,
             };
-            cmd.arg(ssh_opts);
-            cmd.arg(format!("{}:{}", ssh_target(profile), remote_path));
-            cmd.arg(absolute_dest.to_string_lossy().to_string());
+            let session = session.options(transfer.options());
+
+            if watch {
+                println!("Watching {source} → {alias_name}:{remote_path} (Ctrl-C to stop)");
+                if let Err(err) = watch::watch(session, &absolute_source, remote_path, &options) {
+                    eprintln!("\n{err}");
+                    std::process::exit(err.exit_code());
+                }
+            } else {
+                if dry_run {
+                    println!("Dry run, nothing will be changed:");
+                }
+                println!("Syncing {source} → {alias_name}:{remote_path}");
 
-            if !cmd.status().map(|s| s.success()).unwrap_or(false) {
-                eprintln!("\n✗ Transfer failed");
-                std::process::exit(1);
+                let result =
+                    session
+                        .progress(!dry_run)
+                        .sync(&absolute_source, remote_path, &options);
+                exit_on_transfer_error(result);
             }
         }
 
         Commands::Exec {
-            alias: alias_name,
+            alias: target,
+            raw,
+            tty,
+            no_tty,
+            cd,
+            env,
             cmd,
         } => {
             if cmd.is_empty() {
                 eprintln!("No command specified");
-                std::process::exit(1);
+                std::process::exit(EXIT_USAGE);
             }
+            // Quoted, every argument arrives as it was given here
+            let cmd = if raw { cmd.join(" ") } else { shell_join(&cmd) };
 
-            let profile = config.get_profile(&alias_name).unwrap_or_else(|err| {
+            let aliases = config.resolve_targets(&target).unwrap_or_else(|err| {
                 eprintln!("{err}");
-                std::process::exit(1);
+                std::process::exit(err.exit_code());
+            });
+            override_workdir(&mut config, &aliases, cd, env);
+
+            let tty = match (tty, no_tty) {
+                (true, _) if aliases.len() > 1 => {
+                    eprintln!("--tty only works with a single host");
+                    std::process::exit(EXIT_USAGE);
+                }
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                // Several hosts can't share our terminal
+                _ if aliases.len() > 1 => Some(false),
+                // Like ssh for a shell: a terminal only when there is one
+                _ => Some(io::stdin().is_terminal() && io::stdout().is_terminal()),
+            };
+            run_on_targets(&config, &aliases, &cmd, None, tty, format);
+        }
+
+        Commands::Run {
+            interpreter,
+            env,
+            args,
+        } => {
+            // The target is optional, so the first argument is only taken as
+            // one if it names hosts and isn't the script itself
+            let (target, rest) = match args.split_first() {
+                Some((first, rest))
+                    if !rest.is_empty()
+                        && !Path::new(first).is_file()
+                        && config.resolve_targets(first).is_ok() =>
+                {
+                    (first.as_str(), rest)
+                }
+                _ => ("default", &args[..]),
+            };
+            let (script, script_args) = rest.split_first().expect("clap requires a script");
+
+            let source = fs::read(script).unwrap_or_else(|e| {
+                eprintln!("Error: Failed to read script '{script}': {e}");
+                std::process::exit(EXIT_FAILURE);
             });
+            let interpreter = match interpreter {
+                Some(program) if !program.trim().is_empty() => {
+                    program.split_whitespace().map(str::to_string).collect()
+                }
+                _ => script::interpreter(&source),
+            };
 
-            let mut ssh_cmd = Command::new("ssh");
-            ssh_cmd.args(setup_multiplex());
-            if profile.port != 22 {
-                ssh_cmd.arg("-p").arg(profile.port.to_string());
+            let aliases = config.resolve_targets(target).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+            let remote_cmd = script::remote_command(&interpreter, &env, script_args);
+            let input = Some(Path::new(script));
+            // stdin carries the script, a terminal would mangle it
+            run_on_targets(&config, &aliases, &remote_cmd, input, Some(false), format);
+        }
+
+        Commands::Forward {
+            alias,
+            names,
+            local,
+            remote,
+            dynamic,
+            cancel,
+            save,
+            forget,
+            list,
+        } => {
+            let alias = config.resolve_alias(&alias).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+
+            if list {
+                let profile = &config.profiles[&alias];
+                if profile.forwards.is_empty() {
+                    println!("No saved forwards for '{alias}'. Save one with --save <name>.");
+                    return;
+                }
+
+                let mut saved: Vec<_> = profile.forwards.iter().collect();
+                saved.sort_by_key(|(name, _)| name.as_str());
+                println!("Saved forwards for {alias}:\n");
+                for (name, forward) in saved {
+                    println!("  {name}: {forward}");
+                }
+                return;
+            }
+
+            if let Some(name) = forget {
+                let profile = config.profiles.get_mut(&alias).expect("alias was resolved");
+                if profile.forwards.remove(&name).is_none() {
+                    eprintln!("No saved forward named '{name}' for '{alias}'");
+                    std::process::exit(EXIT_FAILURE);
+                }
+                if let Err(e) = config.save() {
+                    eprintln!("Error saving config: {e}");
+                    std::process::exit(e.exit_code());
+                }
+                println!("✓ Removed saved forward '{name}'");
+                return;
+            }
+
+            let mut forwards: Vec<Forward> = Vec::new();
+            for (kind, specs) in [
+                (ForwardKind::Local, local),
+                (ForwardKind::Remote, remote),
+                (ForwardKind::Dynamic, dynamic),
+            ] {
+                forwards.extend(specs.into_iter().map(|spec| Forward { kind, spec }));
+            }
+
+            if let Some(name) = save {
+                if forwards.len() != 1 {
+                    eprintln!("Error: --save needs exactly one -L, -R or -D forward");
+                    std::process::exit(EXIT_USAGE);
+                }
+                let profile = config.profiles.get_mut(&alias).expect("alias was resolved");
+                profile.forwards.insert(name.clone(), forwards[0].clone());
+                if let Err(e) = config.save() {
+                    eprintln!("Error saving config: {e}");
+                    std::process::exit(e.exit_code());
+                }
+                println!("✓ Saved forward '{name}': {}", forwards[0]);
+            }
+
+            let profile = &config.profiles[&alias];
+            if forwards.is_empty() {
+                if names.is_empty() {
+                    forwards = profile.forwards.values().cloned().collect();
+                    forwards.sort_by(|a, b| a.spec.cmp(&b.spec));
+                } else {
+                    for name in &names {
+                        match profile.forwards.get(name) {
+                            Some(forward) => forwards.push(forward.clone()),
+                            None => {
+                                eprintln!("No saved forward named '{name}' for '{alias}'");
+                                std::process::exit(EXIT_FAILURE);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if forwards.is_empty() {
+                eprintln!("No forwards given and none saved for '{alias}'");
+                eprintln!("Usage: qs forward {alias} -L 5432:localhost:5432");
+                std::process::exit(EXIT_USAGE);
+            }
+
+            if !config.settings_for(profile).multiplex() {
+                if cancel {
+                    println!(
+                        "Multiplexing is disabled for {alias}, stop the forwarding ssh instead"
+                    );
+                    return;
+                }
+
+                // Without a master connection the forwards live in a foreground ssh
+                let args = ssh_args(&config, profile).unwrap_or_else(|err| {
+                    eprintln!("{err}");
+                    std::process::exit(err.exit_code());
+                });
+
+                let mut cmd = Command::new("ssh");
+                cmd.arg("-N");
+                cmd.args(setup_multiplex(&config.settings_for(profile)));
+                cmd.args(args);
+                for forward in &forwards {
+                    println!("Forwarding {forward}");
+                    cmd.arg(forward.flag()).arg(&forward.spec);
+                }
+                cmd.arg(ssh_target(profile));
+                println!("Press Ctrl+C to stop");
+
+                exit_with_status(Process.status(&mut cmd), "ssh");
+                return;
+            }
+
+            let running = master_running(&config, &Process, profile).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+
+            if cancel && !running {
+                println!("No active connection to {alias}, nothing to cancel");
+                return;
+            }
+
+            if !running {
+                println!("Opening master connection to {alias}...");
+                if let Err(err) = start_master(&config, &Process, profile) {
+                    eprintln!("✗ {err}");
+                    std::process::exit(err.exit_code());
+                }
+            }
+
+            let mut failed = false;
+            for forward in &forwards {
+                match forward_port(&config, &Process, profile, forward, cancel) {
+                    Ok(_) if cancel => println!("✓ Cancelled {forward}"),
+                    Ok(_) => println!("✓ Forwarding {forward}"),
+                    Err(err) => {
+                        failed = true;
+                        eprintln!("✗ {forward}: {err}");
+                    }
+                }
+            }
+
+            if failed {
+                std::process::exit(EXIT_TRANSPORT);
+            }
+            if !cancel {
+                println!("\nForwards stay open until cancelled with 'qs forward {alias} --cancel'");
             }
-            ssh_cmd.arg(ssh_target(profile));
-            ssh_cmd.arg(cmd.join(" "));
-            ssh_cmd.status().ok();
         }
 
-        Commands::Status { alias } => {
-            let profile = config.get_profile(&alias).unwrap_or_else(|err| {
+        Commands::Group { action } => {
+            match action {
+                GroupCommands::Add { name, aliases } => {
+                    if let Err(error) = add_group(&mut config, &name, &aliases) {
+                        eprintln!("Error: {error}");
+                        std::process::exit(error.exit_code());
+                    }
+                    if let Err(e) = config.save() {
+                        eprintln!("Error saving config: {e}");
+                        std::process::exit(e.exit_code());
+                    }
+                    println!("✓ Saved group {name}: {}", aliases.join(", "));
+                }
+
+                GroupCommands::Remove { name } => {
+                    if let Err(error) = remove_group(&mut config, &name) {
+                        eprintln!("{error}");
+                        std::process::exit(error.exit_code());
+                    }
+                    if let Err(e) = config.save() {
+                        eprintln!("Error saving config: {e}");
+                        std::process::exit(e.exit_code());
+                    }
+                    println!("✓ Removed group '{name}'");
+                }
+
+                GroupCommands::List => {
+                    if config.groups.is_empty() {
+                        println!("No groups configured. Use 'qs group add <name> <alias>...' to add one.");
+                        return;
+                    }
+
+                    println!("Configured groups:\n");
+                    for (name, members) in &config.groups {
+                        println!("  {name}: {}", members.join(", "));
+                    }
+                }
+            }
+        }
+
+        Commands::Status { alias, all, clean } => {
+            if all {
+                let mut aliases: Vec<_> = config.profiles.keys().cloned().collect();
+                aliases.sort();
+
+                let mut owners: HashMap<PathBuf, String> = HashMap::new();
+                let mut hosts = Vec::new();
+                for alias in &aliases {
+                    let profile = &config.profiles[alias];
+                    if let Ok(Some(path)) = control_path(&config, &Process, profile) {
+                        owners.insert(path, alias.clone());
+                    }
+                    hosts.push(host_status(&config, alias));
+                }
+
+                let mut sockets = Vec::new();
+                let dirs = socket_dirs(&config);
+                for dir in &dirs {
+                    for socket in socket_files(dir) {
+                        let pid = socket_pid(&Process, &socket);
+                        sockets.push(SocketRecord {
+                            owner: owners.get(&socket).cloned(),
+                            stale: pid.is_none(),
+                            pid,
+                            path: socket,
+                        });
+                    }
+                }
+
+                let stale: Vec<_> = sockets.iter().filter(|s| s.stale).collect();
+                let removed = if clean {
+                    stale
+                        .iter()
+                        .filter(|s| fs::remove_file(&s.path).is_ok())
+                        .count()
+                } else {
+                    0
+                };
+
+                if let Some(data) = format.data() {
+                    print!("{}", render(&StatusReport { hosts, sockets }, data));
+                    return;
+                }
+
+                let width = aliases.iter().map(|a| a.len()).max().unwrap_or(0);
+                if !hosts.is_empty() {
+                    println!("Hosts:\n");
+                }
+                for host in &hosts {
+                    let alias = &host.alias;
+                    match (&host.error, host.pid) {
+                        _ if !host.multiplex => {
+                            println!("  {alias:<width$}  - Multiplexing disabled")
+                        }
+                        (Some(err), _) => println!("  {alias:<width$}  ✗ {err}"),
+                        (None, Some(pid)) => println!("  {alias:<width$}  ✓ Active (pid {pid})"),
+                        (None, None) => println!("  {alias:<width$}  ✗ No active connection"),
+                    }
+                }
+
+                for dir in &dirs {
+                    println!("\nSockets in {}:\n", dir.display());
+                    let in_dir: Vec<_> = sockets
+                        .iter()
+                        .filter(|s| s.path.parent() == Some(dir.as_path()))
+                        .collect();
+                    if in_dir.is_empty() {
+                        println!("  No socket files");
+                    }
+
+                    for socket in in_dir {
+                        let name = socket
+                            .path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        let owner = socket
+                            .owner
+                            .as_ref()
+                            .map(|alias| format!(" ({alias})"))
+                            .unwrap_or_default();
+
+                        match socket.pid {
+                            Some(pid) => println!("  {name}  ✓ pid {pid}{owner}"),
+                            None => println!("  {name}  ✗ stale{owner}"),
+                        }
+                    }
+                }
+
+                if stale.is_empty() {
+                    return;
+                }
+
+                if clean {
+                    println!("\n✓ Removed {removed} stale socket(s)");
+                } else {
+                    println!(
+                        "\n{} stale socket(s). Run 'qs status --all --clean' to remove them.",
+                        stale.len()
+                    );
+                }
+                return;
+            }
+
+            let resolved = config.resolve_alias(&alias).unwrap_or_else(|err| {
                 eprintln!("{err}");
-                std::process::exit(1);
+                std::process::exit(err.exit_code());
             });
+            if let Some(data) = format.data() {
+                print!("{}", render(&host_status(&config, &resolved), data));
+                return;
+            }
 
             let display_alias = if alias == "default" {
-                match config.default.as_ref() {
-                    Some(actual_alias) => format!("{actual_alias} [default]"),
-                    None => alias,
-                }
+                format!("{resolved} [default]")
             } else {
                 alias
             };
 
+            if !config.settings_for(&config.profiles[&resolved]).multiplex() {
+                println!(
+                    "Multiplexing is disabled for {display_alias}, there is no master connection"
+                );
+                return;
+            }
+
             print!("Checking connection to {display_alias}... ");
             io::stdout().flush().unwrap_or(());
 
-            let mut cmd = Command::new("ssh");
-            cmd.args(setup_multiplex());
-            if profile.port != 22 {
-                cmd.arg("-p").arg(profile.port.to_string());
+            let status = host_status(&config, &resolved);
+            match (status.error, status.pid) {
+                (Some(err), _) => {
+                    println!();
+                    eprintln!("{err}");
+                    std::process::exit(EXIT_TRANSPORT);
+                }
+                (None, Some(pid)) => println!("✓ Active (pid {pid})"),
+                (None, None) => println!("✗ No active connection"),
             }
-            cmd.arg("-O");
-            cmd.arg("check");
-            cmd.arg(ssh_target(profile));
-            cmd.stdout(Stdio::null());
-            cmd.stderr(Stdio::null());
+        }
 
-            match cmd.status() {
-                Ok(status) if status.success() => println!("✓ Active"),
-                _ => println!("✗ No active connection"),
+        Commands::Close { alias, all } => {
+            if all {
+                let closed = socket_dirs(&config)
+                    .iter()
+                    .flat_map(|dir| socket_files(dir))
+                    .filter(|s| close_socket(&Process, s))
+                    .count();
+                println!("✓ Closed {closed} master connection(s)");
+                return;
+            }
+
+            let session = Session::from_config(&config, &alias).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(err.exit_code());
+            });
+
+            match session.close() {
+                Ok(true) => println!("✓ Closed connection to {}", session.alias()),
+                Ok(false) => println!("No active connection to {}", session.alias()),
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(err.exit_code());
+                }
+            }
+        }
+
+        Commands::Keys { action } => {
+            match action {
+                KeyCommands::Rotate { key_type, yes } => {
+                    if config.profiles.is_empty() {
+                        println!("No hosts configured.");
+                        return;
+                    }
+
+                    // Hosts sharing a key are rotated together
+                    let mut by_key: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+                    for (alias, profile) in &config.profiles {
+                        by_key
+                            .entry(profile_key_path(profile))
+                            .or_default()
+                            .push(alias.clone());
+                    }
+                    for aliases in by_key.values_mut() {
+                        aliases.sort();
+                    }
+
+                    println!("Keys to rotate:");
+                    for (key, aliases) in &by_key {
+                        println!("  {} → {}", key.display(), aliases.join(", "));
+                    }
+
+                    if !yes && !confirm("\nRotate these keys?") {
+                        println!("Rotation cancelled");
+                        return;
+                    }
+
+                    let mut complete = true;
+                    for (key, aliases) in &by_key {
+                        println!("\nRotating {}...", key.display());
+
+                        let report = match rotate_key(&config, &Process, key, aliases, key_type) {
+                            Ok(report) => report,
+                            Err(err) => {
+                                eprintln!("✗ {err}");
+                                complete = false;
+                                continue;
+                            }
+                        };
+
+                        for (alias, outcome) in &report.hosts {
+                            match outcome {
+                                HostOutcome::Rotated => println!("  ✓ {alias}"),
+                                HostOutcome::OldKeyKept(err) => {
+                                    println!("  ⚠ {alias}: new key installed, old key not removed ({err})")
+                                }
+                                HostOutcome::Failed(err) => println!("  ✗ {alias}: {err}"),
+                            }
+                        }
+
+                        if report.retired {
+                            println!(
+                                "✓ New key in place, old key kept at {}.old",
+                                report.key.display()
+                            );
+                        } else {
+                            complete = false;
+                            let rotated = report
+                                .hosts
+                                .iter()
+                                .filter(|(_, o)| *o == HostOutcome::Rotated)
+                                .count();
+                            eprintln!(
+                                "⚠ Partial rotation: {rotated}/{} host(s) accept the new key. \
+                             The old key is still active everywhere; the new one is kept \
+                             at {}.new for the next run.",
+                                report.hosts.len(),
+                                report.key.display()
+                            );
+                        }
+                    }
+
+                    if !complete {
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                }
+
+                KeyCommands::Revoke { alias, yes } => {
+                    let alias = config.resolve_alias(&alias).unwrap_or_else(|err| {
+                        eprintln!("{err}");
+                        std::process::exit(err.exit_code());
+                    });
+                    if let Err(err) = revoke_host_key(&config, &alias, yes) {
+                        eprintln!("✗ Failed to revoke key on {alias}: {err}");
+                        std::process::exit(err.exit_code());
+                    }
+                }
             }
         }
 
@@ -342,15 +1107,220 @@ fn main() {
                 for alias_name in config.profiles.keys() {
                     eprintln!("  - {alias_name}");
                 }
-                std::process::exit(1);
+                std::process::exit(EXIT_ALIAS);
             }
 
             config.default = Some(alias.clone());
             if let Err(e) = config.save() {
                 eprintln!("Error saving config: {e}");
-                std::process::exit(1);
+                std::process::exit(e.exit_code());
             }
+            sync_ssh_config(&config);
             println!("✓ Set {alias} as default");
         }
     }
 }
+
+/// Master connection state of a configured alias.
+fn host_status(config: &Config, alias: &str) -> StatusRecord {
+    let status = Session::from_config(config, alias).and_then(|session| session.status());
+    let (multiplex, pid, error) = match status {
+        Ok(MasterStatus::Active(pid)) => (true, Some(pid), None),
+        Ok(MasterStatus::Inactive) => (true, None, None),
+        Ok(MasterStatus::Disabled) => (false, None, None),
+        Err(err) => {
+            let multiplex = config.settings_for(&config.profiles[alias]).multiplex();
+            (multiplex, None, Some(err.to_string()))
+        }
+    };
+
+    StatusRecord {
+        alias: alias.to_string(),
+        multiplex,
+        active: pid.is_some(),
+        pid,
+        error,
+    }
+}
+
+fn confirm(prompt: &str) -> bool {
+    print!("{prompt} [y/N]: ");
+    io::stdout().flush().unwrap_or(());
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap_or_else(|_| {
+        eprintln!("Failed to read input");
+        std::process::exit(EXIT_FAILURE);
+    });
+
+    let input = input.trim().to_lowercase();
+    input == "y" || input == "yes"
+}
+
+/// Removes the profile's key from the host. Returns false if the user
+/// cancelled.
+fn revoke_host_key(config: &Config, alias: &str, yes: bool) -> Result<bool, Error> {
+    let profile = &config.profiles[alias];
+    let key = profile_key_path(profile);
+
+    // Without the key, the next login needs a password or some other key
+    if key_logs_in(config, &Process, profile, &key) {
+        println!(
+            "⚠ {} is the key used to log in to '{alias}'.",
+            key.display()
+        );
+        if !yes && !confirm("Revoke it anyway?") {
+            println!("Revoke cancelled");
+            return Ok(false);
+        }
+    }
+
+    if revoke_key(config, &Process, profile, &key)? {
+        println!("✓ Revoked {} on {alias}", key.display());
+    } else {
+        println!("Key {} is not installed on {alias}", key.display());
+    }
+    Ok(true)
+}
+
+/// Waits on the outcome of a local ssh or rsync run. A non-zero exit is
+/// returned as is, so remote exit codes reach our caller.
+fn run_status(status: io::Result<ExitStatus>, program: &str) -> Result<(), i32> {
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(status.code().unwrap_or(EXIT_FAILURE)),
+        Err(e) => {
+            eprintln!("Failed to run {program}: {e}");
+            Err(EXIT_TRANSPORT)
+        }
+    }
+}
+
+/// Decides how `session` copies files, and says so if it can't be rsync.
+fn settle_transfer_method(session: Session) -> Session {
+    let method = session.transfer_method().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(err.exit_code());
+    });
+    if session.profile().transfer.is_none() && method != Transfer::Rsync {
+        println!(
+            "rsync isn't installed on both ends, using {} instead",
+            method.program()
+        );
+    }
+
+    session.transfer(method)
+}
+
+/// Applies `--cd` and `--env` to the profiles of `aliases`, over their own
+/// `workdir` and `env`.
+fn override_workdir(
+    config: &mut Config,
+    aliases: &[String],
+    cd: Option<String>,
+    env: Vec<(String, String)>,
+) {
+    for alias in aliases {
+        if let Some(profile) = config.profiles.get_mut(alias) {
+            if cd.is_some() {
+                profile.workdir = cd.clone();
+            }
+            profile.env.extend(env.iter().cloned());
+        }
+    }
+}
+
+/// Runs `remote_cmd` on one or several hosts and exits with the remote exit
+/// code, the first failing one for several hosts. Hosts read `input` on
+/// stdin if given. A single host otherwise reads our stdin, with a terminal
+/// if `tty` says so.
+fn run_on_targets(
+    config: &Config,
+    aliases: &[String],
+    remote_cmd: &str,
+    input: Option<&Path>,
+    tty: Option<bool>,
+    format: OutputFormat,
+) {
+    if let Some(data) = format.data() {
+        let records = match aliases {
+            [alias] => vec![capture_on_host(config, alias, remote_cmd, input)],
+            _ => capture_on_hosts(config, &Process, aliases, remote_cmd, input),
+        };
+        print!("{}", render(&records, data));
+
+        let failure = records.iter().find_map(|r| match r.exit_code {
+            Some(0) => None,
+            Some(code) => Some(code),
+            None => Some(EXIT_TRANSPORT),
+        });
+        std::process::exit(failure.unwrap_or(0));
+    }
+
+    if aliases.len() > 1 {
+        let results = exec_on_hosts(config, &Process, aliases, remote_cmd, input);
+
+        println!("\nSummary:");
+        // The first failing host decides the exit code
+        let mut exit_code = 0;
+        for (alias, code) in &results {
+            let failure = match code {
+                Some(0) => {
+                    println!("  ✓ {alias}: exit 0");
+                    continue;
+                }
+                Some(code) => {
+                    println!("  ✗ {alias}: exit {code}");
+                    *code
+                }
+                None => {
+                    println!("  ✗ {alias}: no exit code");
+                    EXIT_TRANSPORT
+                }
+            };
+            if exit_code == 0 {
+                exit_code = failure;
+            }
+        }
+
+        std::process::exit(exit_code);
+    }
+
+    let status = Session::from_config(config, &aliases[0])
+        .map(|session| session.tty(tty))
+        .and_then(|session| match input {
+            Some(path) => File::open(path)
+                .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", path.display())))
+                .and_then(|file| session.run_with_input(remote_cmd, file)),
+            None => session.run(Some(remote_cmd)),
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(err.exit_code());
+        });
+    exit_with_status(Ok(status), "ssh");
+}
+
+/// Exits with the transfer program's code, or the error's, if `send`, `get`
+/// or `sync` failed.
+fn exit_on_transfer_error(result: Result<(), Error>) {
+    if let Err(err) = result {
+        if !matches!(err, Error::Transfer { .. }) {
+            eprintln!("{err}");
+        }
+        eprintln!("\n✗ Transfer failed");
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn exit_with_status(status: io::Result<ExitStatus>, program: &str) {
+    if let Err(code) = run_status(status, program) {
+        std::process::exit(code);
+    }
+}
+
+fn sync_ssh_config(config: &Config) {
+    if let Err(e) = sync_managed_ssh_config(config) {
+        eprintln!("⚠ Failed to update the exported ssh config: {e}");
+    }
+}
@@ -0,0 +1,562 @@
+//! Built-in SSH client for systems without the `ssh` and `rsync` binaries,
+//! enabled with the `native-ssh` feature.
+//!
+//! It covers what a [`Session`](crate::session::Session) does plus key
+//! installation. Files are copied over SFTP. There is no connection
+//! multiplexing, so every call opens its own connection.
+
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use ssh2::{Channel, CheckResult, FileStat, KnownHostFileKind, Sftp};
+
+use crate::config::{Config, Profile};
+use crate::error::Error;
+use crate::keys::{
+    install_key_script, install_outcome, remote_result, InstallOutcome, SSHD_CONFIG,
+};
+use crate::util::{profile_key_path, ssh_dir};
+
+/// An authenticated connection to one host.
+pub struct Connection {
+    session: ssh2::Session,
+}
+
+impl Connection {
+    /// Connects and logs in with the profile's key or the ssh agent.
+    pub fn open(config: &Config, profile: &Profile) -> Result<Self, Error> {
+        let conn = Self::handshake(config, profile)?;
+        if conn.login_with_key(profile) {
+            Ok(conn)
+        } else {
+            Err(Error::Transport("Authentication failed".to_string()))
+        }
+    }
+
+    /// Like [`open`](Self::open), but asks for the password if no key works.
+    pub fn open_with_password(config: &Config, profile: &Profile) -> Result<Self, Error> {
+        let conn = Self::handshake(config, profile)?;
+        if conn.login_with_key(profile) {
+            return Ok(conn);
+        }
+
+        let prompt = format!("{}@{}'s password: ", profile.user, profile.host);
+        let password = rpassword::prompt_password(prompt)
+            .map_err(|e| Error::Transport(format!("Failed to read password: {e}")))?;
+        conn.session
+            .userauth_password(&profile.user, &password)
+            .map_err(|_| Error::Transport("Authentication failed".to_string()))?;
+
+        Ok(conn)
+    }
+
+    fn handshake(config: &Config, profile: &Profile) -> Result<Self, Error> {
+        if profile.jump.is_some() {
+            return Err(Error::Transport(
+                "Jump hosts need the ssh binary, the built-in client can't use them".to_string(),
+            ));
+        }
+
+        let settings = config.settings_for(profile);
+        let timeout = settings
+            .connect_timeout
+            .map(|t| Duration::from_secs(t.into()));
+        let tcp = connect_tcp(&profile.host, profile.port, timeout)?;
+
+        let mut session = ssh2::Session::new().map_err(transport_error)?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(transport_error)?;
+        if let Some(interval) = settings.server_alive_interval {
+            session.set_keepalive(false, interval);
+        }
+
+        verify_host_key(&session, &profile.host, profile.port)?;
+        Ok(Self { session })
+    }
+
+    fn login_with_key(&self, profile: &Profile) -> bool {
+        let key = profile_key_path(profile);
+        if key.exists() {
+            self.session
+                .userauth_pubkey_file(&profile.user, None, &key, None)
+                .ok();
+        }
+        // Same fallback ssh has, unless the profile pins its key
+        if !self.session.authenticated() && profile.identity_file.is_none() {
+            self.session.userauth_agent(&profile.user).ok();
+        }
+
+        self.session.authenticated()
+    }
+
+    /// Runs `remote_cmd` and captures its output.
+    pub fn exec(&self, remote_cmd: &str) -> Result<Output, Error> {
+        self.exec_with_input(remote_cmd, io::empty())
+    }
+
+    /// Like [`exec`](Connection::exec), with `input` as the command's stdin.
+    pub fn exec_with_input(
+        &self,
+        remote_cmd: &str,
+        input: impl Read + Send + 'static,
+    ) -> Result<Output, Error> {
+        let mut channel = self.session.channel_session().map_err(transport_error)?;
+        channel.exec(remote_cmd).map_err(transport_error)?;
+
+        // Both streams are read as they come, a full one would stall the other
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        self.pump(&mut channel, input, &mut stdout, &mut stderr)?;
+
+        Ok(Output {
+            status: exit_status(&mut channel)?,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Runs `remote_cmd`, or an interactive shell, connected to our stdio.
+    /// With `tty`, it gets a terminal and ours is put in raw mode.
+    pub fn run(&self, remote_cmd: Option<&str>, tty: bool) -> Result<ExitStatus, Error> {
+        let mut channel = self.session.channel_session().map_err(transport_error)?;
+
+        if tty {
+            let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string());
+            let (rows, cols) = terminal_size().unwrap_or((24, 80));
+            channel
+                .request_pty(&term, None, Some((cols, rows, 0, 0)))
+                .map_err(transport_error)?;
+        }
+        match remote_cmd {
+            Some(remote_cmd) => channel.exec(remote_cmd).map_err(transport_error)?,
+            None => channel.shell().map_err(transport_error)?,
+        }
+        let _raw = if tty { RawMode::enable() } else { None };
+
+        self.pump(
+            &mut channel,
+            io::stdin(),
+            &mut io::stdout(),
+            &mut io::stderr(),
+        )?;
+        exit_status(&mut channel)
+    }
+
+    /// Runs `remote_cmd` with `input` as its stdin and its output going to
+    /// ours.
+    pub fn run_with_input(
+        &self,
+        remote_cmd: &str,
+        input: impl Read + Send + 'static,
+    ) -> Result<ExitStatus, Error> {
+        let mut channel = self.session.channel_session().map_err(transport_error)?;
+        channel.exec(remote_cmd).map_err(transport_error)?;
+
+        self.pump(&mut channel, input, &mut io::stdout(), &mut io::stderr())?;
+        exit_status(&mut channel)
+    }
+
+    /// Copies `input` to the channel and its output to `stdout` and `stderr`
+    /// until the remote side is done.
+    fn pump(
+        &self,
+        channel: &mut Channel,
+        mut input: impl Read + Send + 'static,
+        stdout: &mut impl Write,
+        stderr: &mut impl Write,
+    ) -> Result<(), Error> {
+        // Reading input blocks, so it happens on its own thread. An empty
+        // chunk marks the end of input.
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                let chunk = match input.read(&mut buf) {
+                    Ok(0) | Err(_) => Vec::new(),
+                    Ok(n) => buf[..n].to_vec(),
+                };
+                let done = chunk.is_empty();
+                if tx.send(chunk).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        // Blocking reads would hold the session and starve the writes
+        self.session.set_blocking(false);
+        let result = pump_loop(channel, rx, stdout, stderr);
+        self.session.set_blocking(true);
+
+        result
+    }
+
+    /// Copies `local` to `remote_path`. Like rsync, a directory is copied
+    /// into `remote_path` if that is a directory itself.
+    pub fn send(&self, local: &Path, remote_path: &str, progress: bool) -> Result<(), Error> {
+        let sftp = self.session.sftp().map_err(transport_error)?;
+        let mut remote = sftp_path(remote_path);
+        if sftp.stat(&remote).map(|s| s.is_dir()).unwrap_or(false) {
+            if let Some(name) = local.file_name() {
+                remote.push(name);
+            }
+        }
+
+        upload(&sftp, local, &remote, progress)
+    }
+
+    /// Copies `remote_path` to `local`, into it if it is a directory.
+    pub fn get(&self, remote_path: &str, local: &Path, progress: bool) -> Result<(), Error> {
+        let sftp = self.session.sftp().map_err(transport_error)?;
+        let remote = sftp_path(remote_path);
+        let stat = sftp.stat(&remote).map_err(|e| transfer_error(&remote, e))?;
+
+        let mut local = local.to_path_buf();
+        if local.is_dir() {
+            if let Some(name) = remote.file_name() {
+                local.push(name);
+            }
+        }
+
+        download(&sftp, &remote, &stat, &local, progress)
+    }
+}
+
+/// Installs `pubkey` on the host, logging in with a password if needed.
+pub fn install_key(
+    config: &Config,
+    profile: &Profile,
+    pubkey: &str,
+) -> Result<InstallOutcome, Error> {
+    let conn = Connection::open_with_password(config, profile)?;
+    let output = conn.exec(&install_key_script(pubkey, SSHD_CONFIG))?;
+    install_outcome(&remote_result(&output)?)
+}
+
+fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream, Error> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| Error::Transport(format!("Could not resolve {host}: {e}")))?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        let result = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(Error::Transport(match last_error {
+        Some(e) => format!("Failed to connect to {host} port {port}: {e}"),
+        None => format!("Could not resolve {host}"),
+    }))
+}
+
+/// Checks the host key against `~/.ssh/known_hosts`. Unknown hosts are
+/// added, like `StrictHostKeyChecking=accept-new`; changed keys are refused.
+fn verify_host_key(session: &ssh2::Session, host: &str, port: u16) -> Result<(), Error> {
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| Error::Transport(format!("{host} sent no host key")))?;
+
+    let path = ssh_dir().join("known_hosts");
+    let mut known_hosts = session.known_hosts().map_err(transport_error)?;
+    if path.exists() {
+        known_hosts
+            .read_file(&path, KnownHostFileKind::OpenSSH)
+            .map_err(transport_error)?;
+    }
+
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => {
+            let name = if port == 22 {
+                host.to_string()
+            } else {
+                format!("[{host}]:{port}")
+            };
+            fs::create_dir_all(ssh_dir()).ok();
+            known_hosts
+                .add(&name, key, "", key_type.into())
+                .and_then(|_| known_hosts.write_file(&path, KnownHostFileKind::OpenSSH))
+                .map_err(transport_error)?;
+            eprintln!("Permanently added '{name}' to the list of known hosts.");
+            Ok(())
+        }
+        CheckResult::Mismatch => Err(Error::Transport(format!(
+            "Host key verification failed: the key for {host} has changed. Check {} if this is expected",
+            path.display()
+        ))),
+        CheckResult::Failure => Err(Error::Transport(format!(
+            "Host key verification failed for {host}"
+        ))),
+    }
+}
+
+fn pump_loop(
+    channel: &mut Channel,
+    stdin: mpsc::Receiver<Vec<u8>>,
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> Result<(), Error> {
+    let mut buf = [0; 32 * 1024];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut stdin_open = true;
+
+    loop {
+        let mut busy = copy_available(channel, stdout, &mut buf)?;
+        busy |= copy_available(&mut channel.stderr(), stderr, &mut buf)?;
+
+        if stdin_open && pending.is_empty() {
+            match stdin.try_recv() {
+                Ok(chunk) if chunk.is_empty() => stdin_open = false,
+                Ok(chunk) => pending = chunk,
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => stdin_open = false,
+            }
+            if !stdin_open {
+                retry(|| channel.send_eof().map_err(io::Error::from))?;
+            }
+        }
+        if !pending.is_empty() {
+            match channel.write(&pending) {
+                Ok(n) => {
+                    pending.drain(..n);
+                    busy = true;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+
+        if !busy && channel.eof() {
+            return Ok(());
+        }
+        if !busy {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Copies whatever `source` has ready to `dest`. Returns whether there was
+/// anything.
+fn copy_available(
+    source: &mut impl Read,
+    dest: &mut impl Write,
+    buf: &mut [u8],
+) -> Result<bool, Error> {
+    match source.read(buf) {
+        Ok(0) => Ok(false),
+        Ok(n) => {
+            dest.write_all(&buf[..n]).and_then(|_| dest.flush()).ok();
+            Ok(true)
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn retry(mut op: impl FnMut() -> io::Result<()>) -> Result<(), Error> {
+    loop {
+        match op() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            result => return result.map_err(io_error),
+        }
+    }
+}
+
+/// Waits for the channel to close and returns the remote exit status. A
+/// command killed by a signal counts as 255, like ssh reports it.
+fn exit_status(channel: &mut Channel) -> Result<ExitStatus, Error> {
+    channel.wait_close().map_err(transport_error)?;
+
+    let killed = channel
+        .exit_signal()
+        .map(|s| s.exit_signal.is_some())
+        .unwrap_or(false);
+    let code = if killed {
+        255
+    } else {
+        channel.exit_status().map_err(transport_error)?
+    };
+
+    Ok(ExitStatus::from_raw(code << 8))
+}
+
+/// SFTP resolves relative paths against the login directory, but doesn't
+/// know about `~`.
+fn sftp_path(remote_path: &str) -> PathBuf {
+    match remote_path {
+        "" | "~" => PathBuf::from("."),
+        path => PathBuf::from(path.strip_prefix("~/").unwrap_or(path)),
+    }
+}
+
+fn upload(sftp: &Sftp, local: &Path, remote: &Path, progress: bool) -> Result<(), Error> {
+    let meta = fs::metadata(local)
+        .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", local.display())))?;
+    let mode = meta.permissions().mode() & 0o7777;
+
+    if meta.is_dir() {
+        if !sftp.stat(remote).map(|s| s.is_dir()).unwrap_or(false) {
+            sftp.mkdir(remote, mode as i32)
+                .map_err(|e| transfer_error(remote, e))?;
+        }
+
+        let mut entries: Vec<_> = fs::read_dir(local)
+            .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", local.display())))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        entries.sort();
+        for entry in entries {
+            if let Some(name) = entry.file_name() {
+                upload(sftp, &entry, &remote.join(name), progress)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut source = File::open(local)
+        .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", local.display())))?;
+    let mut dest = sftp.create(remote).map_err(|e| transfer_error(remote, e))?;
+    let size = io::copy(&mut source, &mut dest).map_err(io_error)?;
+    drop(dest);
+
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    let stat = FileStat {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: Some(mode),
+        atime: mtime,
+        mtime,
+    };
+    sftp.setstat(remote, stat).ok();
+
+    if progress {
+        println!("{} ({size} bytes)", local.display());
+    }
+    Ok(())
+}
+
+fn download(
+    sftp: &Sftp,
+    remote: &Path,
+    stat: &FileStat,
+    local: &Path,
+    progress: bool,
+) -> Result<(), Error> {
+    if stat.is_dir() {
+        fs::create_dir_all(local)
+            .map_err(|e| Error::Transport(format!("Failed to create {}: {e}", local.display())))?;
+
+        let mut entries = sftp
+            .readdir(remote)
+            .map_err(|e| transfer_error(remote, e))?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, stat) in entries {
+            if let Some(name) = path.file_name() {
+                download(sftp, &path, &stat, &local.join(name), progress)?;
+            }
+        }
+    } else {
+        let mut source = sftp.open(remote).map_err(|e| transfer_error(remote, e))?;
+        let mut dest = File::create(local)
+            .map_err(|e| Error::Transport(format!("Failed to create {}: {e}", local.display())))?;
+        let size = io::copy(&mut source, &mut dest).map_err(io_error)?;
+
+        if let Some(mtime) = stat.mtime {
+            dest.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+                .ok();
+        }
+        if progress {
+            println!("{} ({size} bytes)", remote.display());
+        }
+    }
+
+    if let Some(perm) = stat.perm {
+        fs::set_permissions(local, fs::Permissions::from_mode(perm & 0o7777)).ok();
+    }
+    Ok(())
+}
+
+fn transport_error(e: ssh2::Error) -> Error {
+    Error::Transport(e.message().to_string())
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Transport(e.to_string())
+}
+
+fn transfer_error(path: &Path, e: ssh2::Error) -> Error {
+    Error::Transport(format!("{}: {}", path.display(), e.message()))
+}
+
+/// Rows and columns of the terminal, as `stty size` reports them.
+fn terminal_size() -> Option<(u32, u32)> {
+    let output = Command::new("stty")
+        .arg("size")
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+    let size = String::from_utf8_lossy(&output.stdout);
+    let mut parts = size.split_whitespace().map(|n| n.parse().ok());
+
+    Some((parts.next()??, parts.next()??))
+}
+
+/// Puts the terminal in raw mode so keystrokes reach the remote shell as
+/// typed. The previous mode is restored on drop.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+
+        let output = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .ok()?;
+        let saved = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        let status = Command::new("stty")
+            .args(["raw", "-echo"])
+            .stdin(Stdio::inherit())
+            .status()
+            .ok()?;
+        status.success().then_some(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        Command::new("stty")
+            .arg(&self.saved)
+            .stdin(Stdio::inherit())
+            .status()
+            .ok();
+    }
+}
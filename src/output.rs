@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::config::{Multiplexer, Profile};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable text
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+/// A configured host as printed by `qs list`.
+#[derive(Serialize, Debug)]
+pub struct HostRecord {
+    pub alias: String,
+    pub default: bool,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// Master connection state of a host, as printed by `qs status`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StatusRecord {
+    pub alias: String,
+    pub multiplex: bool,
+    pub active: bool,
+    pub pid: Option<u32>,
+    /// Why the state couldn't be checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A control socket file found by `qs status --all`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SocketRecord {
+    pub path: PathBuf,
+    pub owner: Option<String>,
+    pub pid: Option<u32>,
+    pub stale: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StatusReport {
+    pub hosts: Vec<StatusRecord>,
+    pub sockets: Vec<SocketRecord>,
+}
+
+/// A persistent shell on a host, as printed by `qs sessions`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SessionRecord {
+    pub name: String,
+    pub multiplexer: Multiplexer,
+    pub attached: bool,
+}
+
+/// The captured result of running a command on one host.
+#[derive(Serialize, Debug)]
+pub struct ExecRecord {
+    pub alias: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` if ssh couldn't be started or was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+}
+
+/// The formats `render` can produce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataFormat {
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    /// The serialized format to print, or `None` for table output, which each
+    /// command prints itself.
+    pub fn data(self) -> Option<DataFormat> {
+        match self {
+            OutputFormat::Table => None,
+            OutputFormat::Json => Some(DataFormat::Json),
+            OutputFormat::Yaml => Some(DataFormat::Yaml),
+        }
+    }
+}
+
+/// Serializes `value` for the json and yaml formats.
+pub fn render<T: Serialize>(value: &T, format: DataFormat) -> String {
+    // None of the records can fail to serialize: all map keys are strings
+    match format {
+        DataFormat::Json => serde_json::to_string_pretty(value).expect("valid json") + "\n",
+        DataFormat::Yaml => serde_yaml::to_string(value).expect("valid yaml"),
+    }
+}
@@ -0,0 +1,52 @@
+//! `qs run`: runs a local script on a host by streaming it to the stdin of a
+//! remote interpreter, so nothing is left behind on the host.
+
+use std::path::Path;
+
+use crate::util::shell_join;
+
+/// The interpreter named by the script's `#!` line, split into words, or
+/// `sh` without one.
+pub fn interpreter(script: &[u8]) -> Vec<String> {
+    let first_line = script.split(|&b| b == b'\n').next().unwrap_or_default();
+    let first_line = String::from_utf8_lossy(first_line);
+
+    match first_line.strip_prefix("#!") {
+        Some(rest) if !rest.trim().is_empty() => {
+            rest.split_whitespace().map(str::to_string).collect()
+        }
+        _ => vec!["sh".to_string()],
+    }
+}
+
+/// The command line that runs `interpreter` on the script read from stdin,
+/// with `args` as the script's arguments and `env` set.
+pub fn remote_command(interpreter: &[String], env: &[(String, String)], args: &[String]) -> String {
+    let mut words = Vec::new();
+    if !env.is_empty() {
+        words.push("env".to_string());
+        words.extend(env.iter().map(|(key, value)| format!("{key}={value}")));
+    }
+    words.extend(interpreter.iter().cloned());
+
+    // Shells read the script from stdin with -s, most other interpreters
+    // (python, perl, ruby, node) take - as the script's file name
+    if is_shell(interpreter) {
+        words.extend(["-s".to_string(), "--".to_string()]);
+    } else {
+        words.push("-".to_string());
+    }
+    words.extend(args.iter().cloned());
+
+    shell_join(&words)
+}
+
+fn is_shell(interpreter: &[String]) -> bool {
+    // For `/usr/bin/env [-i] [K=V] python3`, look past env to the program
+    let program = interpreter
+        .iter()
+        .filter_map(|word| Path::new(word).file_name()?.to_str())
+        .find(|name| *name != "env" && !name.starts_with('-') && !name.contains('='));
+
+    program.is_some_and(|name| name.ends_with("sh"))
+}
@@ -0,0 +1,693 @@
+//! A connection to one configured host, for using qs from other Rust code.
+//!
+//! ```no_run
+//! use qs::config::Config;
+//! use qs::session::Session;
+//!
+//! let config = Config::load()?;
+//! let session = Session::from_config(&config, "web")?;
+//!
+//! let output = session.exec("uptime")?;
+//! println!("{}", String::from_utf8_lossy(&output.stdout));
+//! # Ok::<(), qs::error::Error>(())
+//! ```
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::Arc;
+
+use tempfile::NamedTempFile;
+
+use crate::attach::{attach_command, list_command, parse_sessions};
+use crate::config::{Config, Multiplexer, Profile, Transfer};
+use crate::error::Error;
+#[cfg(feature = "native-ssh")]
+use crate::native::Connection;
+use crate::output::SessionRecord;
+use crate::transport::{Process, Transport};
+use crate::util::{
+    check_result, control_command, rsync_ssh_command, scp_args, setup_multiplex, sftp_batch,
+    ssh_args, ssh_target, with_workdir,
+};
+
+/// State of the multiplexed master connection to a host.
+#[derive(Debug, PartialEq)]
+pub enum MasterStatus {
+    Disabled,
+    Inactive,
+    /// The master is up. The PID is 0 if ssh didn't report one.
+    Active(u32),
+}
+
+/// Options for every kind of transfer. rsync gets all of them, scp and sftp
+/// the ones they have an equivalent for.
+#[derive(Debug, Default, Clone)]
+pub struct TransferOptions {
+    /// Bandwidth limit in KiB per second.
+    pub bwlimit: Option<u32>,
+    /// Keep partly copied files so an interrupted transfer can resume.
+    pub partial: bool,
+    /// Copy permissions even if the profile's `rsync_args` turn that off.
+    pub preserve_perms: bool,
+    /// rsync compression level, 0 to 9.
+    pub compress_level: Option<u32>,
+    /// Extra rsync flags, after the profile's `rsync_args`.
+    pub rsync_args: Vec<String>,
+}
+
+impl TransferOptions {
+    pub fn rsync_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(limit) = self.bwlimit {
+            args.push(format!("--bwlimit={limit}"));
+        }
+        if self.partial {
+            args.push("--partial".to_string());
+        }
+        if self.preserve_perms {
+            args.push("--perms".to_string());
+        }
+        if let Some(level) = self.compress_level {
+            args.push(format!("--compress-level={level}"));
+        }
+        args.extend(self.rsync_args.iter().cloned());
+
+        args
+    }
+}
+
+/// What `qs sync` copies and how.
+#[derive(Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Delete remote files that don't exist locally.
+    pub delete: bool,
+    /// rsync patterns of files to leave out.
+    pub exclude: Vec<String>,
+    /// Files with one exclude pattern per line.
+    pub exclude_from: Vec<PathBuf>,
+    /// Leave out what `.gitignore` files in the tree ignore.
+    pub gitignore: bool,
+    /// Only list what would change.
+    pub dry_run: bool,
+    /// Compare files by checksum rather than size and modification time.
+    pub checksum: bool,
+}
+
+impl SyncOptions {
+    pub fn rsync_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if self.delete {
+            args.push("--delete".to_string());
+        }
+        if self.checksum {
+            args.push("--checksum".to_string());
+        }
+        if self.dry_run {
+            args.push("--dry-run".to_string());
+            args.push("--itemize-changes".to_string());
+        }
+        // Watch mode ignores changes under .git, so full syncs leave it out too
+        args.push("--exclude=.git".to_string());
+        for pattern in &self.exclude {
+            args.push(format!("--exclude={pattern}"));
+        }
+        for file in &self.exclude_from {
+            args.push(format!("--exclude-from={}", file.display()));
+        }
+        if self.gitignore {
+            // Reads .gitignore in every directory, and doesn't copy them
+            args.push("--filter=:- .gitignore".to_string());
+        }
+
+        args
+    }
+}
+
+/// A host with everything needed to reach it: its profile plus the config it
+/// came from, for connection settings and jump hosts.
+///
+/// Every call goes through the same master connection the `qs` command uses,
+/// so only the first one pays for authentication.
+pub struct Session {
+    alias: String,
+    profile: Profile,
+    config: Config,
+    progress: bool,
+    tty: Option<bool>,
+    options: TransferOptions,
+    transport: Arc<dyn Transport>,
+    /// Use the built-in client instead of running ssh and rsync.
+    #[cfg(feature = "native-ssh")]
+    native: bool,
+}
+
+impl Session {
+    /// A session for a profile that isn't part of a config. Default connection
+    /// settings apply and a `jump` is used as a literal `-J` value.
+    pub fn new(alias: &str, profile: Profile) -> Self {
+        Self {
+            alias: alias.to_string(),
+            profile,
+            config: Config::default(),
+            progress: false,
+            tty: None,
+            options: TransferOptions::default(),
+            transport: Arc::new(Process),
+            #[cfg(feature = "native-ssh")]
+            native: crate::util::native_backend(),
+        }
+    }
+
+    /// A session for a configured alias (or `default`).
+    pub fn from_config(config: &Config, alias: &str) -> Result<Self, Error> {
+        let alias = config.resolve_alias(alias)?;
+        let profile = config.profiles[&alias].clone();
+        // Catch broken jump chains now rather than on first use
+        config.resolve_jump(&profile)?;
+
+        Ok(Self {
+            alias,
+            profile,
+            config: config.clone(),
+            progress: false,
+            tty: None,
+            options: TransferOptions::default(),
+            transport: Arc::new(Process),
+            #[cfg(feature = "native-ssh")]
+            native: crate::util::native_backend(),
+        })
+    }
+
+    /// Show rsync's progress output for `send` and `get`.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Force a remote terminal on (`ssh -tt`) or off (`ssh -T`) for commands.
+    /// By default ssh decides: a terminal for a shell, none for a command.
+    pub fn tty(mut self, tty: Option<bool>) -> Self {
+        self.tty = tty;
+        self
+    }
+
+    /// Options for `send`, `get` and `sync`.
+    pub fn options(mut self, options: TransferOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Copy files with `method`, whatever the profile's `transfer` setting.
+    pub fn transfer(mut self, method: Transfer) -> Self {
+        self.profile.transfer = Some(method);
+        self
+    }
+
+    /// Run commands through `transport` instead of as local processes.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        #[cfg(feature = "native-ssh")]
+        {
+            self.native = false;
+        }
+        self
+    }
+
+    #[cfg(feature = "native-ssh")]
+    fn native(&self) -> Result<Option<Connection>, Error> {
+        if self.native {
+            Connection::open(&self.config, &self.profile).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// An ssh command for the host, running `remote_cmd` or, without one, an
+    /// interactive shell. Either starts in the profile's `workdir` with its
+    /// `env` set. Stdio is inherited unless the caller changes it.
+    pub fn command(&self, remote_cmd: Option<&str>) -> Result<Command, Error> {
+        self.command_with_tty(remote_cmd, self.tty)
+    }
+
+    fn command_with_tty(
+        &self,
+        remote_cmd: Option<&str>,
+        tty: Option<bool>,
+    ) -> Result<Command, Error> {
+        let wrapped = with_workdir(&self.profile, remote_cmd)?;
+        let tty = match tty {
+            // The shell is started by a command then, and ssh gives commands
+            // no terminal by default
+            None if remote_cmd.is_none() && wrapped.is_some() => Some(true),
+            tty => tty,
+        };
+
+        self.ssh_command(wrapped.as_deref(), tty)
+    }
+
+    /// Like [`command`](Session::command), but without the profile's
+    /// `workdir` and `env`, for qs's own probes.
+    fn ssh_command(&self, remote_cmd: Option<&str>, tty: Option<bool>) -> Result<Command, Error> {
+        let mut cmd = Command::new("ssh");
+        cmd.args(setup_multiplex(&self.config.settings_for(&self.profile)));
+        cmd.args(ssh_args(&self.config, &self.profile)?);
+        match tty {
+            // Twice, so it also works when stdin isn't a terminal
+            Some(true) => cmd.arg("-tt"),
+            Some(false) => cmd.arg("-T"),
+            None => &mut cmd,
+        };
+        cmd.arg(ssh_target(&self.profile));
+        if let Some(remote_cmd) = remote_cmd {
+            cmd.arg(remote_cmd);
+        }
+
+        Ok(cmd)
+    }
+
+    /// Runs `remote_cmd`, or an interactive shell, attached to the terminal.
+    /// Returns ssh's exit status, which is the remote command's.
+    pub fn run(&self, remote_cmd: Option<&str>) -> Result<ExitStatus, Error> {
+        self.run_with_tty(remote_cmd, self.tty)
+    }
+
+    fn run_with_tty(
+        &self,
+        remote_cmd: Option<&str>,
+        tty: Option<bool>,
+    ) -> Result<ExitStatus, Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            let tty = tty.unwrap_or(remote_cmd.is_none());
+            let remote_cmd = with_workdir(&self.profile, remote_cmd)?;
+            return conn.run(remote_cmd.as_deref(), tty);
+        }
+
+        self.transport
+            .status(&mut self.command_with_tty(remote_cmd, tty)?)
+            .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))
+    }
+
+    /// Attaches to the persistent shell `name`, creating it in the profile's
+    /// `workdir` if it doesn't exist yet. Uses the profile's `session`
+    /// multiplexer, tmux if unset.
+    pub fn attach(&self, name: &str) -> Result<ExitStatus, Error> {
+        let multiplexer = self.profile.session.unwrap_or(Multiplexer::Tmux);
+        self.run_with_tty(Some(&attach_command(multiplexer, name)), Some(true))
+    }
+
+    /// Lists the persistent shells on the host. Without a `session` setting
+    /// in the profile, both tmux and screen are asked.
+    pub fn sessions(&self) -> Result<Vec<SessionRecord>, Error> {
+        let multiplexers = match self.profile.session {
+            Some(multiplexer) => vec![multiplexer],
+            None => vec![Multiplexer::Tmux, Multiplexer::Screen],
+        };
+
+        let mut sessions = Vec::new();
+        for multiplexer in multiplexers {
+            let output = self.probe(list_command(multiplexer))?;
+            if output.status.code() == Some(255) {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(Error::Transport(match stderr.trim() {
+                    "" => format!("Failed to connect to {}", self.profile.host),
+                    message => message.to_string(),
+                }));
+            }
+            sessions.extend(parse_sessions(
+                multiplexer,
+                &String::from_utf8_lossy(&output.stdout),
+            ));
+        }
+
+        Ok(sessions)
+    }
+
+    /// Runs `remote_cmd` with `input` as its stdin. Output goes to the
+    /// terminal. Returns the remote command's exit status.
+    pub fn run_with_input(&self, remote_cmd: &str, input: File) -> Result<ExitStatus, Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            let remote_cmd = with_workdir(&self.profile, Some(remote_cmd))?;
+            return conn.run_with_input(remote_cmd.as_deref().unwrap_or_default(), input);
+        }
+
+        let mut cmd = self.command(Some(remote_cmd))?;
+        cmd.stdin(input);
+        self.transport
+            .status(&mut cmd)
+            .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))
+    }
+
+    /// Opens the master connection if it isn't up yet. It stays open for
+    /// `control_persist` after the last use.
+    pub fn connect(&self) -> Result<(), Error> {
+        #[cfg(feature = "native-ssh")]
+        if self.native()?.is_some() {
+            return Ok(());
+        }
+
+        let mut cmd = self.ssh_command(Some("true"), None)?;
+        cmd.stdin(Stdio::null());
+        let status = self
+            .transport
+            .status(&mut cmd)
+            .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Transport(format!(
+                "Failed to connect to {}",
+                self.profile.host
+            )))
+        }
+    }
+
+    /// Runs `remote_cmd` and captures its output. A non-zero exit is not an
+    /// error here; check `status` on the result. ssh itself exits with 255
+    /// when it can't connect.
+    pub fn exec(&self, remote_cmd: &str) -> Result<Output, Error> {
+        self.capture(remote_cmd, None)
+    }
+
+    /// Like [`exec`](Session::exec), with `input` as the command's stdin.
+    pub fn exec_with_input(&self, remote_cmd: &str, input: File) -> Result<Output, Error> {
+        self.capture(remote_cmd, Some(input))
+    }
+
+    fn capture(&self, remote_cmd: &str, input: Option<File>) -> Result<Output, Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            let remote_cmd = with_workdir(&self.profile, Some(remote_cmd))?;
+            let remote_cmd = remote_cmd.as_deref().unwrap_or_default();
+            return match input {
+                Some(input) => conn.exec_with_input(remote_cmd, input),
+                None => conn.exec(remote_cmd),
+            };
+        }
+
+        let mut cmd = self.command(Some(remote_cmd))?;
+        cmd.stdin(input.map_or_else(Stdio::null, Stdio::from));
+        self.transport
+            .output(&mut cmd)
+            .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))
+    }
+
+    /// Copies a local file or directory to `remote_path` on the host.
+    pub fn send(&self, local: &Path, remote_path: &str) -> Result<(), Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            return conn.send(local, remote_path, self.progress);
+        }
+
+        self.copy(local, remote_path, true)
+    }
+
+    /// Copies `remote_path` from the host to a local path.
+    pub fn get(&self, remote_path: &str, local: &Path) -> Result<(), Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            return conn.get(remote_path, local, self.progress);
+        }
+
+        self.copy(local, remote_path, false)
+    }
+
+    /// How `send` and `get` copy files: the profile's `transfer` setting, or
+    /// else rsync if both ends have it, then scp, then sftp. Checking the
+    /// remote end runs a command over the master connection.
+    pub fn transfer_method(&self) -> Result<Transfer, Error> {
+        if let Some(method) = self.profile.transfer {
+            return Ok(method);
+        }
+        #[cfg(feature = "native-ssh")]
+        if self.native {
+            return Ok(Transfer::Sftp);
+        }
+
+        if self.transport.installed("rsync") && self.remote_has_rsync()? {
+            Ok(Transfer::Rsync)
+        } else if self.transport.installed("scp") {
+            Ok(Transfer::Scp)
+        } else {
+            Ok(Transfer::Sftp)
+        }
+    }
+
+    fn remote_has_rsync(&self) -> Result<bool, Error> {
+        let output = self.probe("command -v rsync")?;
+        // If ssh can't connect there is nothing to detect. Rsync will report
+        // the connection problem itself.
+        Ok(matches!(output.status.code(), Some(0) | Some(255)))
+    }
+
+    /// Runs one of qs's own commands and captures its output, outside the
+    /// profile's `workdir` and `env`.
+    fn probe(&self, remote_cmd: &str) -> Result<Output, Error> {
+        #[cfg(feature = "native-ssh")]
+        if let Some(conn) = self.native()? {
+            return conn.exec(remote_cmd);
+        }
+
+        let mut cmd = self.ssh_command(Some(remote_cmd), None)?;
+        cmd.stdin(Stdio::null());
+        self.transport
+            .output(&mut cmd)
+            .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))
+    }
+
+    fn copy(&self, local: &Path, remote_path: &str, upload: bool) -> Result<(), Error> {
+        let method = self.transfer_method()?;
+        if method != Transfer::Rsync && !self.options.rsync_args.is_empty() {
+            return Err(Error::Dependency(vec!["rsync".to_string()]));
+        }
+        let target = ssh_target(&self.profile);
+        // Deleted when dropped, after the transfer
+        let mut _batch_file = None;
+
+        let mut cmd = match method {
+            Transfer::Rsync => self.rsync_command()?,
+            _ => Command::new(method.program()),
+        };
+        match method {
+            Transfer::Rsync => {}
+            Transfer::Scp => {
+                // Keep modes and times, like rsync's archive mode
+                cmd.args(["-r", "-p"]);
+                if !self.progress {
+                    cmd.arg("-q");
+                }
+                cmd.args(self.limit_args());
+                cmd.args(scp_args(&self.config, &self.profile)?);
+            }
+            Transfer::Sftp => {
+                let batch = sftp_batch(
+                    &local.to_string_lossy(),
+                    remote_path,
+                    upload,
+                    self.progress,
+                    self.options.partial,
+                );
+                let file = temp_file("sftp", batch.as_bytes())?;
+
+                cmd.arg("-b").arg(file.path());
+                cmd.args(self.limit_args());
+                cmd.args(scp_args(&self.config, &self.profile)?);
+                cmd.arg(&target);
+                _batch_file = Some(file);
+            }
+        }
+
+        if method != Transfer::Sftp {
+            let remote = format!("{target}:{remote_path}");
+            if upload {
+                cmd.arg(local).arg(remote);
+            } else {
+                cmd.arg(remote).arg(local);
+            }
+        }
+
+        self.run_transfer(&mut cmd, method.program())
+    }
+
+    /// `-l` for scp and sftp, which take the limit in Kbit/s.
+    fn limit_args(&self) -> Vec<String> {
+        match self.options.bwlimit {
+            Some(limit) => vec!["-l".to_string(), (u64::from(limit) * 8).to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Makes `remote_path` a mirror of the local directory `local`: its
+    /// contents end up directly in `remote_path`. `.git` directories are left
+    /// out. Needs rsync on both ends.
+    pub fn sync(
+        &self,
+        local: &Path,
+        remote_path: &str,
+        options: &SyncOptions,
+    ) -> Result<(), Error> {
+        self.mirror(local, remote_path, options, &[])
+    }
+
+    /// Like [`sync`](Session::sync), but only copies `paths`, given relative
+    /// to `local`. Directories in `paths` are copied whole. Paths that no
+    /// longer exist locally are deleted on the host with `options.delete`,
+    /// and skipped without it.
+    pub fn sync_paths(
+        &self,
+        local: &Path,
+        remote_path: &str,
+        paths: &[PathBuf],
+        options: &SyncOptions,
+    ) -> Result<(), Error> {
+        let mut list = Vec::new();
+        for path in paths {
+            list.extend_from_slice(path.as_os_str().as_encoded_bytes());
+            list.push(0);
+        }
+        let list_file = temp_file("files", &list)?;
+
+        let missing = if options.delete {
+            "--delete-missing-args"
+        } else {
+            "--ignore-missing-args"
+        };
+        let extra = [
+            // --files-from turns off the recursion -a implies
+            "-r".to_string(),
+            "--from0".to_string(),
+            format!("--files-from={}", list_file.path().display()),
+            missing.to_string(),
+        ];
+
+        self.mirror(local, remote_path, options, &extra)
+    }
+
+    fn mirror(
+        &self,
+        local: &Path,
+        remote_path: &str,
+        options: &SyncOptions,
+        extra: &[String],
+    ) -> Result<(), Error> {
+        if self.transfer_method()? != Transfer::Rsync {
+            return Err(Error::Dependency(vec!["rsync".to_string()]));
+        }
+
+        let mut cmd = self.rsync_command()?;
+        cmd.args(options.rsync_args());
+        cmd.args(extra);
+
+        // The trailing slash copies the directory's contents, not the directory
+        let mut source = local.as_os_str().to_owned();
+        if !source.to_string_lossy().ends_with('/') {
+            source.push("/");
+        }
+        cmd.arg(source);
+        cmd.arg(format!("{}:{remote_path}", ssh_target(&self.profile)));
+
+        self.run_transfer(&mut cmd, "rsync")
+    }
+
+    /// `rsync` with the options every transfer uses. The caller adds the
+    /// source and destination.
+    fn rsync_command(&self) -> Result<Command, Error> {
+        let mut cmd = Command::new("rsync");
+        cmd.arg("-az");
+        if self.progress {
+            cmd.arg("--progress");
+        }
+        cmd.arg("-e");
+        cmd.arg(rsync_ssh_command(&self.config, &self.profile)?);
+        cmd.args(&self.profile.rsync_args);
+        cmd.args(self.options.rsync_args());
+
+        Ok(cmd)
+    }
+
+    fn run_transfer(&self, cmd: &mut Command, program: &str) -> Result<(), Error> {
+        let status = self
+            .transport
+            .status(cmd)
+            .map_err(|e| Error::Transport(format!("Failed to run {program}: {e}")))?;
+
+        match status.code() {
+            Some(0) => Ok(()),
+            code => Err(Error::Transfer {
+                code: code.unwrap_or(1),
+            }),
+        }
+    }
+
+    /// Checks the master connection without opening one.
+    pub fn status(&self) -> Result<MasterStatus, Error> {
+        #[cfg(feature = "native-ssh")]
+        if self.native {
+            return Ok(MasterStatus::Disabled);
+        }
+
+        if !self.config.settings_for(&self.profile).multiplex() {
+            return Ok(MasterStatus::Disabled);
+        }
+
+        let mut cmd = control_command(&self.config, &self.profile, "check")?;
+        cmd.arg(ssh_target(&self.profile)).stdin(Stdio::null());
+
+        let output = self.transport.output(&mut cmd).ok();
+        Ok(match output.as_ref().and_then(check_result) {
+            Some(pid) => MasterStatus::Active(pid),
+            None => MasterStatus::Inactive,
+        })
+    }
+
+    /// Closes the master connection. Returns whether one was running.
+    pub fn close(&self) -> Result<bool, Error> {
+        #[cfg(feature = "native-ssh")]
+        if self.native {
+            return Ok(false);
+        }
+
+        let mut cmd = control_command(&self.config, &self.profile, "exit")?;
+        cmd.arg(ssh_target(&self.profile));
+        cmd.stdout(Stdio::null()).stderr(Stdio::null());
+
+        Ok(self
+            .transport
+            .status(&mut cmd)
+            .map(|s| s.success())
+            .unwrap_or(false))
+    }
+}
+
+/// Writes `contents` to a new file in the temp directory that only we can
+/// read. It is created under a random name and never through an existing
+/// path, so a link planted there can't redirect the write.
+fn temp_file(kind: &str, contents: &[u8]) -> Result<NamedTempFile, Error> {
+    let write_error = |e: std::io::Error| {
+        Error::Transport(format!("Failed to write a temporary {kind} file: {e}"))
+    };
+
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("qs-{kind}-"))
+        .tempfile()
+        .map_err(write_error)?;
+    file.write_all(contents)
+        .and_then(|_| file.flush())
+        .map_err(write_error)?;
+
+    Ok(file)
+}
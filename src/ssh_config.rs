@@ -0,0 +1,404 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Profile};
+use crate::error::Error;
+use crate::util::{connection_options, expand_home, multiplex_options};
+
+// Same nesting limit OpenSSH applies to Include directives
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A `Host` (or `Match`) block and the options listed under it, in file order.
+struct HostBlock {
+    patterns: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl HostBlock {
+    fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns,
+            options: Vec::new(),
+        }
+    }
+
+    fn matches(&self, alias: &str) -> bool {
+        let alias = alias.to_lowercase();
+        let mut matched = false;
+
+        for pattern in &self.patterns {
+            let pattern = pattern.to_lowercase();
+            if let Some(negated) = pattern.strip_prefix('!') {
+                if wildcard_match(negated, &alias) {
+                    return false;
+                }
+            } else if wildcard_match(&pattern, &alias) {
+                matched = true;
+            }
+        }
+
+        matched
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImportAction {
+    Add,
+    Overwrite,
+    Unchanged,
+    Conflict,
+    Invalid(String),
+}
+
+/// A concrete host alias and its resolved profile, or why it can't be imported.
+pub type ParsedHost = (String, Result<Profile, String>);
+
+pub struct ImportEntry {
+    pub alias: String,
+    pub action: ImportAction,
+    pub profile: Option<Profile>,
+}
+
+pub fn default_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".ssh")
+        .join("config")
+}
+
+/// Location of the qs-managed fragment that is kept in sync with the config.
+pub fn managed_path() -> PathBuf {
+    Config::path().with_file_name("ssh_config")
+}
+
+/// Renders every profile as an OpenSSH `Host` block, including the same
+/// keepalive and multiplexing options qs uses itself.
+pub fn render_ssh_config(config: &Config) -> Result<String, Error> {
+    let mut aliases: Vec<_> = config.profiles.keys().collect();
+    aliases.sort();
+
+    let mut out = String::from("# Generated by qs. Changes made here will be overwritten.\n");
+
+    for alias in aliases {
+        let profile = &config.profiles[alias];
+        // Fail early on broken jump chains rather than writing a config ssh rejects
+        config.resolve_jump(profile)?;
+
+        out.push_str(&format!("\nHost {alias}\n"));
+        push_option(&mut out, "HostName", &profile.host);
+        push_option(&mut out, "User", &profile.user);
+        if profile.port != 22 {
+            push_option(&mut out, "Port", &profile.port.to_string());
+        }
+        if let Some(identity_file) = &profile.identity_file {
+            push_option(&mut out, "IdentityFile", identity_file);
+            push_option(&mut out, "IdentitiesOnly", "yes");
+        }
+        // Aliases are exported too, so ssh can follow chained jumps by name
+        if let Some(jump) = &profile.jump {
+            push_option(&mut out, "ProxyJump", jump);
+        }
+        let settings = config.settings_for(profile);
+        for (key, value) in connection_options(&settings)
+            .into_iter()
+            .chain(multiplex_options(&settings))
+        {
+            push_option(&mut out, key, &value);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Rewrites the managed fragment if it exists. Returns whether it was updated.
+pub fn sync_managed_ssh_config(config: &Config) -> Result<bool, Error> {
+    let path = managed_path();
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    write_managed_ssh_config(config)?;
+    Ok(true)
+}
+
+pub fn write_managed_ssh_config(config: &Config) -> Result<PathBuf, Error> {
+    let path = managed_path();
+    let content = render_ssh_config(config)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::Config(format!("Failed to create config directory: {e}")))?;
+    }
+    fs::write(&path, content)
+        .map_err(|e| Error::Config(format!("Failed to write {}: {e}", path.display())))?;
+
+    Ok(path)
+}
+
+fn push_option(out: &mut String, key: &str, value: &str) {
+    if value.contains(char::is_whitespace) {
+        out.push_str(&format!("    {key} \"{value}\"\n"));
+    } else {
+        out.push_str(&format!("    {key} {value}\n"));
+    }
+}
+
+/// Parses an OpenSSH client config into one profile per concrete `Host` alias.
+///
+/// Options are resolved the way ssh does it: every block matching the alias
+/// contributes, and the first value seen for a keyword wins. Relative `Include`
+/// paths are resolved against the directory of `path`.
+pub fn parse_ssh_config(path: &Path) -> Result<Vec<ParsedHost>, Error> {
+    let base_dir = path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    // Options before the first Host line apply to every host
+    let mut blocks = vec![HostBlock::new(vec!["*".to_string()])];
+    read_config_file(path, &base_dir, &mut blocks, 0)?;
+
+    let mut aliases: Vec<String> = Vec::new();
+    for block in &blocks {
+        for pattern in &block.patterns {
+            let concrete = !pattern.contains(['*', '?']) && !pattern.starts_with('!');
+            if concrete && !aliases.contains(pattern) {
+                aliases.push(pattern.clone());
+            }
+        }
+    }
+
+    Ok(aliases
+        .into_iter()
+        .map(|alias| {
+            let profile = build_profile(&alias, &blocks);
+            (alias, profile)
+        })
+        .collect())
+}
+
+/// Decides what importing each parsed host would do to `config`.
+pub fn plan_import(config: &Config, hosts: Vec<ParsedHost>, overwrite: bool) -> Vec<ImportEntry> {
+    hosts
+        .into_iter()
+        .map(|(alias, profile)| {
+            let action = match &profile {
+                Err(e) => ImportAction::Invalid(e.clone()),
+                Ok(profile) => match crate::util::validate_alias(&alias) {
+                    Err(e) => ImportAction::Invalid(e.to_string()),
+                    Ok(_) if config.groups.contains_key(&alias) => {
+                        ImportAction::Invalid(format!("'{alias}' is already used as a group"))
+                    }
+                    Ok(_) => match config.profiles.get(&alias) {
+                        None => ImportAction::Add,
+                        Some(existing) if existing == profile => ImportAction::Unchanged,
+                        Some(_) if overwrite => ImportAction::Overwrite,
+                        Some(_) => ImportAction::Conflict,
+                    },
+                },
+            };
+
+            ImportEntry {
+                alias,
+                action,
+                profile: profile.ok(),
+            }
+        })
+        .collect()
+}
+
+fn read_config_file(
+    path: &Path,
+    base_dir: &Path,
+    blocks: &mut Vec<HostBlock>,
+    depth: usize,
+) -> Result<(), Error> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Error::Config(format!(
+            "Too many nested Include directives at {}",
+            path.display()
+        )));
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("Failed to read {}: {e}", path.display())))?;
+
+    for line in content.lines() {
+        let Some((key, value)) = split_line(line) else {
+            continue;
+        };
+
+        match key.as_str() {
+            "host" => blocks.push(HostBlock::new(split_args(&value))),
+            // Match criteria aren't evaluated, so nothing inside applies
+            "match" => blocks.push(HostBlock::new(Vec::new())),
+            "include" => {
+                let patterns = blocks.last().map(|b| b.patterns.clone());
+                for file in expand_include(&value, base_dir) {
+                    read_config_file(&file, base_dir, blocks, depth + 1)?;
+                }
+                // Lines after the Include still belong to the enclosing block
+                blocks.push(HostBlock::new(patterns.unwrap_or_default()));
+            }
+            _ => {
+                if let Some(block) = blocks.last_mut() {
+                    block.options.push((key, value));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn build_profile(alias: &str, blocks: &[HostBlock]) -> Result<Profile, String> {
+    let lookup = |key: &str| {
+        blocks
+            .iter()
+            .filter(|block| block.matches(alias))
+            .flat_map(|block| block.options.iter())
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let host = lookup("hostname")
+        .map(|h| h.replace("%h", alias))
+        .unwrap_or_else(|| alias.to_string());
+
+    let user = match lookup("user") {
+        Some(user) => user.to_string(),
+        None => std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .map_err(|_| "No User set and the local user name is unknown".to_string())?,
+    };
+
+    let port = match lookup("port") {
+        Some(port) => port.parse().map_err(|_| format!("Invalid port '{port}'"))?,
+        None => 22,
+    };
+
+    let jump = lookup("proxyjump")
+        .filter(|j| !j.eq_ignore_ascii_case("none"))
+        .map(str::to_string);
+
+    let identity_file = lookup("identityfile")
+        .filter(|f| !f.eq_ignore_ascii_case("none"))
+        .map(expand_home);
+
+    Ok(Profile {
+        host,
+        user,
+        port,
+        jump,
+        identity_file,
+        ..Default::default()
+    })
+}
+
+/// Splits a config line into a lowercase keyword and its value. Keywords and
+/// values may be separated by whitespace and/or a single `=`.
+fn split_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (key, rest) = line.split_at(end);
+
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim();
+    let value = rest
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(rest);
+
+    Some((key.to_lowercase(), value.to_string()))
+}
+
+fn split_args(value: &str) -> Vec<String> {
+    value
+        .split_whitespace()
+        .map(|arg| arg.trim_matches('"').to_string())
+        .filter(|arg| !arg.is_empty())
+        .collect()
+}
+
+/// Expands the paths of an Include directive. Wildcards are supported in the
+/// file name; missing files are skipped like ssh does.
+fn expand_include(value: &str, base_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for pattern in split_args(value) {
+        let path = PathBuf::from(expand_home(&pattern));
+        let path = if path.is_absolute() {
+            path
+        } else {
+            base_dir.join(path)
+        };
+
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if !file_name.contains(['*', '?']) {
+            if path.is_file() {
+                files.push(path);
+            }
+            continue;
+        }
+
+        let Some(dir) = path.parent() else {
+            continue;
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+
+        let mut matched: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|p| p.is_file())
+            .filter(|p| {
+                p.file_name()
+                    .map(|n| wildcard_match(&file_name, &n.to_string_lossy()))
+                    .unwrap_or(false)
+            })
+            .collect();
+        matched.sort();
+        files.extend(matched);
+    }
+
+    files
+}
+
+/// Matches `text` against a pattern where `*` is any run of characters and
+/// `?` is exactly one character.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
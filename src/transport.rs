@@ -0,0 +1,138 @@
+//! Where the ssh and rsync commands built by a [`Session`](crate::session::Session),
+//! and by the commands that work on several hosts or on masters, get run.
+//!
+//! [`Process`] runs them for real. [`Recorder`] only records their argv and
+//! answers with a canned result, so tests can check what would have run
+//! without a reachable host.
+
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+
+use crate::util::check_command;
+
+pub trait Transport: Send + Sync {
+    /// Whether `program` is installed locally.
+    fn installed(&self, program: &str) -> bool {
+        check_command(program)
+    }
+
+    /// Runs `cmd` with the stdio it was set up with and waits for it.
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+
+    /// Runs `cmd` and captures its stdout and stderr.
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+
+    /// Starts `cmd` with its stdout and stderr piped, without waiting for it.
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child>;
+}
+
+/// Runs commands as local processes.
+#[derive(Debug, Default)]
+pub struct Process;
+
+impl Transport for Process {
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+    }
+}
+
+/// Records every command instead of running it. Each one "exits" with the
+/// configured code and output, and every program counts as installed unless
+/// marked [`missing`](Recorder::missing).
+// Only tests record commands, the binary has no use for it
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct Recorder {
+    calls: Mutex<Vec<Vec<String>>>,
+    missing: Vec<String>,
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+#[allow(dead_code)]
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pretend `program` isn't installed. Everything else is.
+    pub fn missing(mut self, program: &str) -> Self {
+        self.missing.push(program.to_string());
+        self
+    }
+
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    pub fn stdout(mut self, stdout: &str) -> Self {
+        self.stdout = stdout.to_string();
+        self
+    }
+
+    pub fn stderr(mut self, stderr: &str) -> Self {
+        self.stderr = stderr.to_string();
+        self
+    }
+
+    /// The argv of every command so far, program first, in the order they ran.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, cmd: &Command) -> ExitStatus {
+        let argv = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        self.calls.lock().unwrap().push(argv);
+
+        // Wait statuses keep the exit code in the second byte
+        ExitStatus::from_raw(self.exit_code << 8)
+    }
+}
+
+impl Transport for Recorder {
+    fn installed(&self, program: &str) -> bool {
+        !self.missing.iter().any(|p| p == program)
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        Ok(self.record(cmd))
+    }
+
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        Ok(Output {
+            status: self.record(cmd),
+            stdout: self.stdout.clone().into_bytes(),
+            stderr: self.stderr.clone().into_bytes(),
+        })
+    }
+
+    fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        self.record(cmd);
+
+        // A real child is needed to read from, so a shell plays the part
+        Command::new("sh")
+            .arg("-c")
+            .arg(r#"printf '%s' "$1"; printf '%s' "$2" >&2; exit "$3""#)
+            .args(["sh", &self.stdout, &self.stderr])
+            .arg(self.exit_code.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+    }
+}
@@ -1,22 +1,37 @@
 use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use crate::config::{Config, ConnectionSettings, Forward, Profile};
+use crate::error::Error;
+use crate::keys::{install_key, read_public_key, InstallOutcome};
+use crate::output::ExecRecord;
+use crate::session::Session;
+use crate::transport::Transport;
+
+pub fn check_dependencies() -> Result<(), Error> {
+    // The built-in client stands in for ssh. ssh-keygen is only needed to
+    // create keys, and that fails with its own error.
+    if cfg!(feature = "native-ssh") {
+        return Ok(());
+    }
 
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-
-use crate::config::{Config, Profile};
-
-pub fn check_dependencies() -> Result<(), String> {
-    let mut missing = Vec::new();
-    let required = vec!["ssh", "ssh-keygen", "rsync"];
+    // rsync is optional: send and get fall back to scp or sftp without it
+    let mut missing: Vec<String> = Vec::new();
+    let required = vec!["ssh", "ssh-keygen"];
 
     for cmd in required {
         if !check_command(cmd) {
-            missing.push(cmd);
+            missing.push(cmd.to_string());
         }
     }
 
     if !missing.is_empty() {
-        return Err(missing_tools_msg(missing));
+        return Err(Error::Dependency(missing));
     }
 
     Ok(())
@@ -37,103 +52,645 @@ pub fn missing_tools_msg(missing: Vec<&str>) -> String {
     msg
 }
 
+/// Whether `cmd` is an executable on the PATH. Doesn't rely on `which`,
+/// which minimal systems may not have either.
 pub fn check_command(cmd: &str) -> bool {
-    Command::new("which")
-        .arg(cmd)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map(|s| s.success())
-        .unwrap_or(false)
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&path).any(|dir| {
+        fs::metadata(dir.join(cmd))
+            .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    })
 }
 
-pub fn setup_multiplex() -> Vec<String> {
-    let socket_dir = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(".ssh")
-        .join("sockets");
+/// Whether ssh operations go through the built-in client instead of the
+/// `ssh` binary. That's only the case when the binary isn't installed.
+#[cfg(feature = "native-ssh")]
+pub fn native_backend() -> bool {
+    !check_command("ssh")
+}
+
+pub fn setup_multiplex(settings: &ConnectionSettings) -> Vec<String> {
+    to_ssh_options(multiplex_options(settings))
+}
+
+/// The ssh options used for connection multiplexing, as keyword/value pairs.
+/// When multiplexing is disabled they turn it off explicitly, so a
+/// `ControlMaster` from the user's own ssh config doesn't kick in either.
+pub fn multiplex_options(settings: &ConnectionSettings) -> Vec<(&'static str, String)> {
+    if !settings.multiplex() {
+        return vec![
+            ("ControlMaster", "no".into()),
+            ("ControlPath", "none".into()),
+        ];
+    }
+
+    let socket_dir = socket_dir(settings);
     fs::create_dir_all(&socket_dir).ok();
 
     vec![
-        "-o".into(),
-        "ControlMaster=auto".into(),
-        "-o".into(),
-        format!("ControlPath={}/%C", socket_dir.display()),
-        "-o".into(),
-        "ControlPersist=10m".into(),
+        ("ControlMaster", "auto".into()),
+        ("ControlPath", format!("{}/%C", socket_dir.display())),
+        ("ControlPersist", settings.control_persist().into()),
     ]
 }
 
+/// Keepalive and timeout options, as keyword/value pairs. Only options that
+/// are set are returned, so ssh's own defaults apply otherwise.
+pub fn connection_options(settings: &ConnectionSettings) -> Vec<(&'static str, String)> {
+    let mut options = Vec::new();
+
+    if let Some(interval) = settings.server_alive_interval {
+        options.push(("ServerAliveInterval", interval.to_string()));
+    }
+    if let Some(count) = settings.server_alive_count_max {
+        options.push(("ServerAliveCountMax", count.to_string()));
+    }
+    if let Some(timeout) = settings.connect_timeout {
+        options.push(("ConnectTimeout", timeout.to_string()));
+    }
+
+    options
+}
+
+fn to_ssh_options(options: Vec<(&'static str, String)>) -> Vec<String> {
+    options
+        .into_iter()
+        .flat_map(|(key, value)| ["-o".to_string(), format!("{key}={value}")])
+        .collect()
+}
+
+pub fn socket_dir(settings: &ConnectionSettings) -> PathBuf {
+    match &settings.socket_dir {
+        Some(dir) => PathBuf::from(expand_home(dir)),
+        None => dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".ssh")
+            .join("sockets"),
+    }
+}
+
+/// Every socket directory in use: the global one plus any profile overrides.
+pub fn socket_dirs(config: &Config) -> Vec<PathBuf> {
+    let mut dirs = vec![socket_dir(&config.settings)];
+
+    let mut profiles: Vec<_> = config.profiles.values().collect();
+    profiles.sort_by(|a, b| a.host.cmp(&b.host));
+    for profile in profiles {
+        let settings = config.settings_for(profile);
+        let dir = socket_dir(&settings);
+        if settings.multiplex() && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    dirs
+}
+
+/// Expands a leading `~` and `%d` to the home directory.
+pub fn expand_home(path: &str) -> String {
+    let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+    let home = home.display().to_string();
+
+    let path = path.replace("%d", &home);
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("{home}/{rest}"),
+        None if path == "~" => home,
+        None => path,
+    }
+}
+
+/// Connection options every ssh invocation for `profile` needs: the port, the
+/// identity file, keepalive and timeout settings and the resolved jump host chain.
+pub fn ssh_args(config: &Config, profile: &Profile) -> Result<Vec<String>, Error> {
+    let mut args = to_ssh_options(connection_options(&config.settings_for(profile)));
+
+    if profile.port != 22 {
+        args.push("-p".to_string());
+        args.push(profile.port.to_string());
+    }
+
+    if let Some(identity_file) = &profile.identity_file {
+        args.push("-i".to_string());
+        args.push(expand_home(identity_file));
+        // Don't let keys from the agent or ssh defaults be offered first
+        args.push("-o".to_string());
+        args.push("IdentitiesOnly=yes".to_string());
+    }
+
+    if let Some(jump) = config.resolve_jump(profile)? {
+        match profile
+            .jump
+            .as_ref()
+            .and_then(|hop| config.profiles.get(hop))
+        {
+            // -J only carries user@host:port, so a bastion with its own key or
+            // settings is reached with a full ssh command of its own
+            Some(hop) if hop_has_options(config, hop) => {
+                let mut proxy = vec!["ssh".to_string()];
+                proxy.extend(ssh_args(config, hop)?);
+                proxy.extend([ssh_target(hop), "-W".to_string(), "%h:%p".to_string()]);
+                args.push("-o".to_string());
+                args.push(format!("ProxyCommand={}", shell_join(&proxy)));
+            }
+            _ => {
+                args.push("-J".to_string());
+                args.push(jump);
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Whether `hop`, or an alias further out along its jump chain, sets its own
+/// identity file or connection options, which a `-J` hop can't carry.
+fn hop_has_options(config: &Config, hop: &Profile) -> bool {
+    let mut next = Some(hop);
+    let mut seen = Vec::new();
+
+    while let Some(hop) = next {
+        if hop.identity_file.is_some() || !connection_options(&hop.settings).is_empty() {
+            return true;
+        }
+        next = hop
+            .jump
+            .as_deref()
+            .filter(|jump| !seen.contains(jump))
+            .and_then(|jump| {
+                seen.push(jump);
+                config.profiles.get(jump)
+            });
+    }
+
+    false
+}
+
+/// Builds the `ssh ...` command string passed to rsync's `-e` option.
+pub fn rsync_ssh_command(config: &Config, profile: &Profile) -> Result<String, Error> {
+    let mut parts = vec!["ssh".to_string()];
+    parts.extend(ssh_args(config, profile)?);
+    parts.extend(setup_multiplex(&config.settings_for(profile)));
+
+    Ok(shell_join(&parts))
+}
+
+/// Options for scp and sftp: the multiplex and connection options ssh gets,
+/// except that these take the port as `-P`.
+pub fn scp_args(config: &Config, profile: &Profile) -> Result<Vec<String>, Error> {
+    let mut args = setup_multiplex(&config.settings_for(profile));
+    args.extend(ssh_args(config, profile)?.into_iter().map(|arg| {
+        if arg == "-p" {
+            "-P".to_string()
+        } else {
+            arg
+        }
+    }));
+
+    Ok(args)
+}
+
+/// Batch commands for `sftp -b` that copy `local` to `remote`, or back with
+/// `upload` false. Directories are copied recursively, with their modes and
+/// times. With `resume`, files that were partly copied before are continued.
+pub fn sftp_batch(local: &str, remote: &str, upload: bool, progress: bool, resume: bool) -> String {
+    let quote = |path: &str| format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""));
+    // sftp knows no `~`, but resolves relative paths against the home directory
+    let remote = match remote {
+        "" | "~" => ".",
+        path => path.strip_prefix("~/").unwrap_or(path),
+    };
+
+    let mut batch = String::new();
+    // Batch mode turns the progress meter off, this turns it back on
+    if progress {
+        batch.push_str("progress\n");
+    }
+    let flags = if resume { "-rpa" } else { "-rp" };
+    if upload {
+        batch.push_str(&format!("put {flags} {} {}\n", quote(local), quote(remote)));
+    } else {
+        batch.push_str(&format!("get {flags} {} {}\n", quote(remote), quote(local)));
+    }
+
+    batch
+}
+
+/// Builds `ssh -O <operation>` for the profile's master connection. The caller
+/// appends any operation arguments and the target.
+pub fn control_command(
+    config: &Config,
+    profile: &Profile,
+    operation: &str,
+) -> Result<Command, Error> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(setup_multiplex(&config.settings_for(profile)));
+    cmd.args(ssh_args(config, profile)?);
+    cmd.arg("-O").arg(operation);
+
+    Ok(cmd)
+}
+
+pub fn master_running(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+) -> Result<bool, Error> {
+    Ok(master_pid(config, transport, profile)?.is_some())
+}
+
+/// Returns the PID of the profile's master connection, or `None` if there is
+/// no live master.
+pub fn master_pid(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+) -> Result<Option<u32>, Error> {
+    let mut cmd = control_command(config, profile, "check")?;
+    cmd.arg(ssh_target(profile));
+
+    Ok(check_output(transport, cmd))
+}
+
+/// Returns the PID of the master listening on a socket file, or `None` if the
+/// socket is stale.
+pub fn socket_pid(transport: &dyn Transport, socket: &Path) -> Option<u32> {
+    check_output(transport, socket_command(socket, "check"))
+}
+
+/// Builds `ssh -O <operation>` for whatever master listens on `socket`.
+fn socket_command(socket: &Path, operation: &str) -> Command {
+    let mut cmd = Command::new("ssh");
+    cmd.arg("-o")
+        .arg(format!("ControlPath={}", socket.display()));
+    // The target is ignored when the control path has no % tokens
+    cmd.args(["-O", operation, "qs"]);
+    cmd
+}
+
+fn check_output(transport: &dyn Transport, mut cmd: Command) -> Option<u32> {
+    check_result(&transport.output(cmd.stdin(Stdio::null())).ok()?)
+}
+
+/// Reads the outcome of an `ssh -O check` run: the master's PID, or `None`
+/// if there is no live master.
+pub fn check_result(output: &Output) -> Option<u32> {
+    if !output.status.success() {
+        return None;
+    }
+
+    // A live master without a parseable PID still counts as running
+    Some(parse_master_pid(&String::from_utf8_lossy(&output.stderr)).unwrap_or(0))
+}
+
+/// Extracts the PID from `ssh -O check` output, e.g. `Master running (pid=1234)`.
+pub fn parse_master_pid(output: &str) -> Option<u32> {
+    let start = output.find("pid=")? + "pid=".len();
+    let digits: String = output[start..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+
+    digits.parse().ok()
+}
+
+/// Asks the ssh client which control socket it would use for the profile.
+pub fn control_path(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+) -> Result<Option<PathBuf>, Error> {
+    let mut cmd = Command::new("ssh");
+    cmd.arg("-G");
+    cmd.args(setup_multiplex(&config.settings_for(profile)));
+    cmd.args(ssh_args(config, profile)?);
+    cmd.arg(ssh_target(profile));
+
+    let output = transport
+        .output(cmd.stdin(Stdio::null()))
+        .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("controlpath "))
+        .map(PathBuf::from))
+}
+
+/// Lists the sockets in a socket directory, sorted by name. Anything else is
+/// left out: the directory is configurable and may hold keys and the like.
+pub fn socket_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut sockets: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_socket()))
+        .map(|entry| entry.path())
+        .collect();
+    sockets.sort();
+
+    sockets
+}
+
+/// Tells the master behind `socket` to exit. Returns whether it was running.
+pub fn close_socket(transport: &dyn Transport, socket: &Path) -> bool {
+    let mut cmd = socket_command(socket, "exit");
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    transport
+        .status(&mut cmd)
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Starts a background master connection that stays up until it is told to
+/// exit, so forwards added to it don't expire with `ControlPersist`.
+pub fn start_master(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+) -> Result<(), Error> {
+    let mut cmd = Command::new("ssh");
+    // Options given first take precedence over the multiplex defaults
+    cmd.args(["-M", "-N", "-f", "-o", "ControlPersist=no"]);
+    cmd.args(setup_multiplex(&config.settings_for(profile)));
+    cmd.args(ssh_args(config, profile)?);
+    cmd.arg(ssh_target(profile));
+
+    match transport.status(&mut cmd) {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(Error::Transport(format!(
+            "Failed to connect to {}",
+            profile.host
+        ))),
+    }
+}
+
+/// Adds a forward to the running master connection, or cancels it.
+pub fn forward_port(
+    config: &Config,
+    transport: &dyn Transport,
+    profile: &Profile,
+    forward: &Forward,
+    cancel: bool,
+) -> Result<(), Error> {
+    let operation = if cancel { "cancel" } else { "forward" };
+    let mut cmd = control_command(config, profile, operation)?;
+    cmd.arg(forward.flag()).arg(&forward.spec);
+    cmd.arg(ssh_target(profile));
+
+    let output = transport
+        .output(&mut cmd)
+        .map_err(|e| Error::Transport(format!("Failed to run ssh: {e}")))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(Error::Transport(
+            stderr
+                .lines()
+                .last()
+                .unwrap_or("ssh failed")
+                .trim()
+                .to_string(),
+        ))
+    }
+}
+
+/// Splits `[alias:]path` into the alias, `default` without one, and the path.
+pub fn split_remote(spec: &str) -> (&str, &str) {
+    spec.split_once(':').unwrap_or(("default", spec))
+}
+
 pub fn ssh_target(profile: &Profile) -> String {
     format!("{}@{}", profile.user, profile.host)
 }
 
-pub fn ensure_ssh_key() -> PathBuf {
-    let ssh_dir = dirs::home_dir()
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum KeyType {
+    Ed25519,
+    Rsa,
+    Ecdsa,
+}
+
+impl KeyType {
+    /// Arguments selecting this key type for `ssh-keygen`.
+    pub fn keygen_args(&self) -> Vec<&'static str> {
+        match self {
+            KeyType::Ed25519 => vec!["-t", "ed25519"],
+            KeyType::Rsa => vec!["-t", "rsa", "-b", "4096"],
+            KeyType::Ecdsa => vec!["-t", "ecdsa", "-b", "521"],
+        }
+    }
+
+    /// The file name ssh itself uses for this key type, e.g. `id_rsa`.
+    pub fn default_file_name(&self) -> &'static str {
+        match self {
+            KeyType::Ed25519 => "id_ed25519",
+            KeyType::Rsa => "id_rsa",
+            KeyType::Ecdsa => "id_ecdsa",
+        }
+    }
+}
+
+pub fn ssh_dir() -> PathBuf {
+    dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
-        .join(".ssh");
-    fs::create_dir_all(&ssh_dir).ok();
+        .join(".ssh")
+}
+
+/// Resolves a key given as a bare file name (relative to `~/.ssh`) or a path.
+pub fn key_path(name: &str) -> PathBuf {
+    if name.contains('/') || name.starts_with('~') {
+        PathBuf::from(expand_home(name))
+    } else {
+        ssh_dir().join(name)
+    }
+}
 
-    let key_path = ssh_dir.join("id_ed25519");
+/// The private key qs authenticates with for a profile.
+pub fn profile_key_path(profile: &Profile) -> PathBuf {
+    match &profile.identity_file {
+        Some(identity_file) => PathBuf::from(expand_home(identity_file)),
+        None => ssh_dir().join(KeyType::Ed25519.default_file_name()),
+    }
+}
 
+/// Returns `key_path`, generating a passphrase-less key of `key_type` there
+/// first if it doesn't exist yet.
+pub fn ensure_key(key_path: &Path, key_type: KeyType) -> Result<PathBuf, Error> {
     if !key_path.exists() {
         println!("No SSH key found. Creating one...");
+        generate_key(key_path, key_type)?;
+        println!("✓ SSH key created at {}", key_path.display());
+    }
 
-        let output = Command::new("ssh-keygen")
-            .args(["-t", "ed25519", "-f"])
-            .arg(&key_path)
-            .args(["-N", ""]) // Empty passphrase
-            .args(["-C", "qs-tool"])
-            .output()
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to generate SSH key: {e}");
-                std::process::exit(1);
-            });
+    Ok(key_path.to_path_buf())
+}
 
-        if output.status.success() {
-            println!("✓ SSH key created at {}", key_path.display());
-        } else {
-            eprintln!("Failed to create SSH key");
-            std::process::exit(1);
+/// Runs `ssh-keygen` to create a passphrase-less key at `key_path`.
+pub fn generate_key(key_path: &Path, key_type: KeyType) -> Result<(), Error> {
+    if let Some(parent) = key_path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+
+    let output = Command::new("ssh-keygen")
+        .args(key_type.keygen_args())
+        .arg("-f")
+        .arg(key_path)
+        .args(["-N", ""]) // Empty passphrase
+        .args(["-C", "qs-tool"])
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Key(format!(
+                "ssh-keygen is needed to create SSH keys. Install it or copy an existing key to {}",
+                key_path.display()
+            )),
+            _ => Error::Key(format!("Failed to generate SSH key: {e}")),
+        })?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::Key("Failed to create SSH key".to_string()))
+    }
+}
+
+/// Path of the public key belonging to `key_path`.
+pub fn public_key_path(key_path: &Path) -> PathBuf {
+    let mut path = key_path.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
+}
+
+/// Quotes `value` for a POSIX shell, so the remote side sees it as one word.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Joins `args` into a command line the remote shell splits back into the
+/// same arguments. Plain words are left unquoted to keep it readable.
+pub fn shell_join(args: &[String]) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=%@:,./".contains(c);
+    args.iter()
+        .map(|arg| {
+            if !arg.is_empty() && arg.chars().all(plain) {
+                arg.clone()
+            } else {
+                shell_quote(arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prefixes `remote_cmd` with a `cd` to the profile's `workdir` and an
+/// `export` of its `env`. Without a command, a login shell is started there.
+/// Returns `remote_cmd` as is if the profile sets neither.
+///
+/// The steps are separate statements rather than joined with `&&`, so an
+/// `||` in `remote_cmd` can't catch a failed `cd`. That one exits instead.
+pub fn with_workdir(profile: &Profile, remote_cmd: Option<&str>) -> Result<Option<String>, Error> {
+    let mut steps = Vec::new();
+
+    if let Some(dir) = &profile.workdir {
+        // Quoted, `~` would be a directory name rather than home
+        let dir = match dir.strip_prefix("~/") {
+            Some(rest) => format!("~/{}", shell_quote(rest)),
+            None if dir == "~" => "~".to_string(),
+            None => shell_quote(dir),
+        };
+        steps.push(format!("cd {dir} || exit 1"));
+    }
+
+    if !profile.env.is_empty() {
+        let mut export = "export".to_string();
+        for (key, value) in &profile.env {
+            parse_env(&format!("{key}=")).map_err(|e| Error::Config(format!("env: {e}")))?;
+            export.push_str(&format!(" {key}={}", shell_quote(value)));
         }
+        steps.push(export);
+    }
+
+    if steps.is_empty() {
+        return Ok(remote_cmd.map(str::to_string));
+    }
+    steps.push(
+        remote_cmd
+            .unwrap_or(r#"exec "${SHELL:-sh}" -l"#)
+            .to_string(),
+    );
+    Ok(Some(steps.join("; ")))
+}
+
+/// Parses a `KEY=VALUE` argument for `--env`.
+pub fn parse_env(spec: &str) -> Result<(String, String), String> {
+    let (key, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{spec}'"))?;
+
+    let valid = !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("'{key}' is not a valid variable name"));
     }
 
-    key_path
+    Ok((key.to_string(), value.to_string()))
+}
+
+pub fn validate_alias(alias: &str) -> Result<(), Error> {
+    validate_name(alias, "Alias")
 }
 
-pub fn validate_alias(alias: &str) -> Result<(), String> {
-    if alias.is_empty() {
-        return Err("Alias cannot be empty".to_string());
+/// Checks an alias or group name, with `noun` naming it in the errors.
+fn validate_name(name: &str, noun: &str) -> Result<(), Error> {
+    if name.is_empty() {
+        return Err(Error::Alias(format!("{noun} cannot be empty")));
     }
 
-    if alias == "default" {
-        return Err("'default' is a reserved alias name".to_string());
+    // Either would shadow the default alias
+    if name == "default" {
+        return Err(Error::Alias(
+            "'default' is a reserved alias name".to_string(),
+        ));
     }
 
-    if alias.contains(':') {
-        return Err("Alias cannot contain ':' character".to_string());
+    if name.contains(':') {
+        return Err(Error::Alias(format!("{noun} cannot contain ':' character")));
     }
 
-    if alias.contains('/') {
-        return Err("Alias cannot contain '/' character".to_string());
+    if name.contains('/') {
+        return Err(Error::Alias(format!("{noun} cannot contain '/' character")));
     }
 
-    if alias.starts_with('-') {
-        return Err("Alias cannot start with '-'".to_string());
+    if name.starts_with('-') {
+        return Err(Error::Alias(format!("{noun} cannot start with '-'")));
     }
 
     Ok(())
 }
 
-pub fn remove_alias(config: &mut Config, alias: &str) -> Result<Vec<String>, String> {
+pub fn remove_alias(config: &mut Config, alias: &str) -> Result<Vec<String>, Error> {
     let mut messages = Vec::new();
 
     if !config.profiles.contains_key(alias) {
-        return Err(format!("Alias '{alias}' not found"));
+        return Err(Error::Alias(format!("Alias '{alias}' not found")));
     }
 
     config.profiles.remove(alias);
 
+    for members in config.groups.values_mut() {
+        members.retain(|member| member != alias);
+    }
+
     if config.default.as_ref() == Some(&alias.to_string()) {
         config.default = None;
         messages.push(format!("✓ Removed default alias '{alias}'"));
@@ -160,44 +717,284 @@ pub fn remove_alias(config: &mut Config, alias: &str) -> Result<Vec<String>, Str
     Ok(messages)
 }
 
-pub fn copy_ssh_key_manual(profile: &Profile) {
-    let key_path = ensure_ssh_key();
-    let pub_key_path = format!("{}.pub", key_path.display());
+pub fn add_group(config: &mut Config, name: &str, aliases: &[String]) -> Result<(), Error> {
+    validate_name(name, "Group name")?;
+
+    if name.contains(',') {
+        return Err(Error::Alias(
+            "Group name cannot contain ',' character".to_string(),
+        ));
+    }
+
+    if config.profiles.contains_key(name) {
+        return Err(Error::Alias(format!(
+            "'{name}' is already used as an alias"
+        )));
+    }
+
+    if aliases.is_empty() {
+        return Err(Error::Alias("A group needs at least one alias".to_string()));
+    }
+
+    let mut members: Vec<String> = Vec::new();
+    for alias in aliases {
+        if !config.profiles.contains_key(alias) {
+            return Err(Error::Alias(format!("Alias '{alias}' doesn't exist")));
+        }
+        if !members.contains(alias) {
+            members.push(alias.clone());
+        }
+    }
+
+    config.groups.insert(name.to_string(), members);
+
+    Ok(())
+}
+
+pub fn remove_group(config: &mut Config, name: &str) -> Result<(), Error> {
+    config
+        .groups
+        .remove(name)
+        .map(|_| ())
+        .ok_or_else(|| Error::Alias(format!("Group '{name}' not found")))
+}
+
+/// Runs `remote_cmd` on every alias at once, printing each output line with a
+/// per-host prefix. Each host reads `input` on stdin if given, nothing
+/// otherwise. Returns the exit code of each host in the given order; `None`
+/// means ssh could not be started or was killed by a signal.
+pub fn exec_on_hosts(
+    config: &Config,
+    transport: &dyn Transport,
+    aliases: &[String],
+    remote_cmd: &str,
+    input: Option<&Path>,
+) -> Vec<(String, Option<i32>)> {
+    let width = aliases.iter().map(|a| a.len()).max().unwrap_or(0);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = aliases
+            .iter()
+            .map(|alias| {
+                let prefix = format!("{alias:<width$} | ");
+                let profile = config.profiles.get(alias);
+
+                scope.spawn(move || {
+                    let mut cmd =
+                        match host_command(config, profile?, remote_cmd).and_then(|mut cmd| {
+                            cmd.stdin(host_input(input)?);
+                            Ok(cmd)
+                        }) {
+                            Ok(cmd) => cmd,
+                            Err(e) => {
+                                eprintln!("{prefix}{e}");
+                                return None;
+                            }
+                        };
+
+                    let mut child = match transport.spawn(&mut cmd) {
+                        Ok(child) => child,
+                        Err(e) => {
+                            eprintln!("{prefix}Failed to start ssh: {e}");
+                            return None;
+                        }
+                    };
+
+                    let stdout = child.stdout.take();
+                    let stderr = child.stderr.take();
+                    thread::scope(|inner| {
+                        if let Some(out) = stdout {
+                            inner.spawn(|| prefix_lines(out, &prefix, false));
+                        }
+                        if let Some(err) = stderr {
+                            inner.spawn(|| prefix_lines(err, &prefix, true));
+                        }
+                    });
+
+                    child.wait().ok().and_then(|status| status.code())
+                })
+            })
+            .collect();
+
+        aliases
+            .iter()
+            .zip(handles)
+            .map(|(alias, handle)| (alias.clone(), handle.join().ok().flatten()))
+            .collect()
+    })
+}
+
+/// Like [`exec_on_hosts`], but captures each host's output instead of
+/// printing it.
+pub fn capture_on_hosts(
+    config: &Config,
+    transport: &dyn Transport,
+    aliases: &[String],
+    remote_cmd: &str,
+    input: Option<&Path>,
+) -> Vec<ExecRecord> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = aliases
+            .iter()
+            .map(|alias| {
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let output = config
+                        .get_profile(alias)
+                        .and_then(|profile| host_command(config, profile, remote_cmd))
+                        .and_then(|mut cmd| {
+                            transport
+                                .output(cmd.stdin(host_input(input)?))
+                                .map_err(|e| Error::Transport(format!("Failed to start ssh: {e}")))
+                        });
+
+                    exec_record(alias, output, start)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("exec thread panicked"))
+            .collect()
+    })
+}
+
+/// Runs `remote_cmd` on a single host and captures its output, through the
+/// host's [`Session`] so the built-in client is used when it's enabled.
+pub fn capture_on_host(
+    config: &Config,
+    alias: &str,
+    remote_cmd: &str,
+    input: Option<&Path>,
+) -> ExecRecord {
+    let start = Instant::now();
+    let output = Session::from_config(config, alias).and_then(|session| match input {
+        Some(path) => fs::File::open(path)
+            .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", path.display())))
+            .and_then(|file| session.exec_with_input(remote_cmd, file)),
+        None => session.exec(remote_cmd),
+    });
+
+    exec_record(alias, output, start)
+}
+
+fn exec_record(alias: &str, output: Result<Output, Error>, start: Instant) -> ExecRecord {
+    let (stdout, stderr, exit_code) = match output {
+        Ok(output) => (
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+            output.status.code(),
+        ),
+        Err(e) => (String::new(), e.to_string(), None),
+    };
+
+    ExecRecord {
+        alias: alias.to_string(),
+        stdout,
+        stderr,
+        exit_code,
+        duration_ms: start.elapsed().as_millis() as u64,
+    }
+}
+
+/// Stdin for one of several hosts: its own handle on `input`, or nothing.
+fn host_input(input: Option<&Path>) -> Result<Stdio, Error> {
+    match input {
+        Some(path) => fs::File::open(path)
+            .map(Stdio::from)
+            .map_err(|e| Error::Transport(format!("Failed to read {}: {e}", path.display()))),
+        None => Ok(Stdio::null()),
+    }
+}
+
+/// ssh invocation running `remote_cmd` on one of several hosts at once.
+fn host_command(config: &Config, profile: &Profile, remote_cmd: &str) -> Result<Command, Error> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(setup_multiplex(&config.settings_for(profile)));
+    // Parallel runs can't share the terminal for password prompts
+    cmd.arg("-o").arg("BatchMode=yes");
+    cmd.args(ssh_args(config, profile)?);
+    cmd.arg(ssh_target(profile));
+    cmd.args(with_workdir(profile, Some(remote_cmd))?);
+
+    Ok(cmd)
+}
 
-    // Read the public key
-    let pubkey = fs::read_to_string(&pub_key_path).expect("Failed to read public key");
+fn prefix_lines(source: impl Read, prefix: &str, to_stderr: bool) {
+    for line in BufReader::new(source).split(b'\n').map_while(Result::ok) {
+        let mut buf = prefix.as_bytes().to_vec();
+        buf.extend_from_slice(&line);
+        buf.push(b'\n');
+
+        // Write the whole line at once so hosts don't interleave mid-line
+        if to_stderr {
+            std::io::stderr().lock().write_all(&buf).ok();
+        } else {
+            let mut out = std::io::stdout().lock();
+            out.write_all(&buf).ok();
+            out.flush().ok();
+        }
+    }
+}
+
+pub fn copy_ssh_key_manual(config: &Config, transport: &dyn Transport, profile: &Profile) {
+    let args = match ssh_args(config, profile) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("⚠ Failed to copy SSH key: {e}");
+            return;
+        }
+    };
+
+    let key_path = match ensure_key(&profile_key_path(profile), KeyType::Ed25519) {
+        Ok(key_path) => key_path,
+        Err(e) => {
+            eprintln!("⚠ Failed to copy SSH key: {e}");
+            return;
+        }
+    };
+    let pub_key_path = public_key_path(&key_path).display().to_string();
+
+    let pubkey = match read_public_key(&key_path) {
+        Ok(pubkey) => pubkey,
+        Err(e) => {
+            eprintln!("⚠ Failed to copy SSH key: {e}");
+            return;
+        }
+    };
 
     println!("Copying SSH key to {}...", profile.host);
     println!("You'll need to enter the password for this host:");
 
-    // Create the SSH command to add the key
-    let remote_cmd = format!(
-        "mkdir -p ~/.ssh && \
-         chmod 700 ~/.ssh && \
-         echo '{}' >> ~/.ssh/authorized_keys && \
-         chmod 600 ~/.ssh/authorized_keys && \
-         echo 'Key added successfully'",
-        pubkey.trim()
-    );
-
     let mut cmd = Command::new("ssh");
     cmd.arg("-o").arg("StrictHostKeyChecking=accept-new");
-    if profile.port != 22 {
-        cmd.arg("-p").arg(profile.port.to_string());
-    }
+    cmd.args(&args);
     cmd.arg(ssh_target(profile));
-    cmd.arg(remote_cmd);
 
-    match cmd.status() {
-        Ok(status) if status.success() => {
-            println!("✓ SSH key copied successfully. No password needed from now on!");
+    #[cfg(feature = "native-ssh")]
+    let result = if native_backend() {
+        crate::native::install_key(config, profile, pubkey.trim())
+    } else {
+        install_key(transport, cmd, pubkey.trim())
+    };
+    #[cfg(not(feature = "native-ssh"))]
+    let result = install_key(transport, cmd, pubkey.trim());
+
+    match result {
+        Ok(InstallOutcome::Added) => {
+            println!("✓ SSH key added. No password needed from now on!");
+        }
+        Ok(InstallOutcome::AlreadyInstalled) => {
+            println!("✓ SSH key already installed on {}", profile.host);
         }
-        _ => {
-            eprintln!("⚠ Failed to copy SSH key. You can try manually with:");
+        Err(err) => {
+            eprintln!("⚠ Failed to copy SSH key: {err}");
+            eprintln!("You can try manually with:");
             eprintln!(
-                "  cat {} | ssh {} 'cat >> ~/.ssh/authorized_keys'",
+                "  ssh-copy-id -i {} {}",
                 pub_key_path,
-                ssh_target(profile)
+                [args, vec![ssh_target(profile)]].concat().join(" ")
             );
         }
     }
@@ -0,0 +1,220 @@
+//! `qs sync --watch`: keeps a remote directory in step with a local one by
+//! pushing whatever changes, as it changes.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::config::Transfer;
+use crate::error::Error;
+use crate::session::{Session, SyncOptions};
+
+/// How long the tree has to be quiet before changes are pushed. Editors and
+/// build tools tend to write several files, or one file several times, in a
+/// burst.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Changed paths waiting to be pushed, relative to the watched directory.
+#[derive(Debug)]
+pub struct Changes {
+    root: PathBuf,
+    paths: BTreeSet<PathBuf>,
+}
+
+impl Changes {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            paths: BTreeSet::new(),
+        }
+    }
+
+    /// Records a changed path. The watched directory itself, paths outside
+    /// of it and anything under `.git` are ignored.
+    pub fn add(&mut self, path: &Path) {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return;
+        };
+        if relative.as_os_str().is_empty()
+            || relative
+                .components()
+                .any(|c| c == Component::Normal(".git".as_ref()))
+        {
+            return;
+        }
+        self.paths.insert(relative.to_path_buf());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Empties the set. Paths under a changed directory are left out, since
+    /// the whole directory is pushed.
+    pub fn take(&mut self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for path in std::mem::take(&mut self.paths) {
+            // Sorted, so a directory comes right before what's under it
+            if !paths.last().is_some_and(|dir| path.starts_with(dir)) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// Puts back paths that couldn't be pushed.
+    pub fn restore(&mut self, paths: Vec<PathBuf>) {
+        self.paths.extend(paths);
+    }
+}
+
+/// Pushes `local` to `remote_path` on the session's host, then pushes every
+/// change until interrupted. Only returns on errors that retrying won't fix.
+pub fn watch(
+    session: Session,
+    local: &Path,
+    remote_path: &str,
+    options: &SyncOptions,
+) -> Result<(), Error> {
+    // Detect once instead of probing the host on every push
+    if session.transfer_method()? != Transfer::Rsync {
+        return Err(Error::Dependency(vec!["rsync".to_string()]));
+    }
+    let session = session.transfer(Transfer::Rsync).progress(false);
+    let destination = format!("{}:{remote_path}", session.alias());
+
+    // Events come with the path that was watched as prefix
+    let local = &local
+        .canonicalize()
+        .map_err(|e| Error::Watch(format!("Failed to watch {}: {e}", local.display())))?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| Error::Watch(format!("Failed to watch {}: {e}", local.display())))?;
+    watcher
+        .watch(local, RecursiveMode::Recursive)
+        .map_err(|e| Error::Watch(format!("Failed to watch {}: {e}", local.display())))?;
+
+    let mut changes = Changes::new(local);
+    // Start with a full sync, and fall back to one whenever events were lost
+    let mut full = true;
+    let mut retry_at: Option<Instant> = None;
+    let mut retry_delay = RETRY_MIN;
+    let mut pushes = 0;
+
+    loop {
+        let wait = match retry_at {
+            Some(at) => Some(at.saturating_duration_since(Instant::now())),
+            None if full || !changes.is_empty() => Some(DEBOUNCE),
+            None => None,
+        };
+        let received = match wait {
+            Some(wait) => rx.recv_timeout(wait),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(Ok(event)) => {
+                if event.need_rescan() {
+                    full = true;
+                } else if !matches!(event.kind, EventKind::Access(_)) {
+                    // Reads don't change anything, and rsync itself reads
+                    // every file it pushes
+                    for path in &event.paths {
+                        changes.add(path);
+                    }
+                }
+                continue;
+            }
+            Ok(Err(_)) => {
+                full = true;
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Error::Watch(format!(
+                    "Stopped receiving changes for {}",
+                    local.display()
+                )));
+            }
+        }
+
+        let paths = changes.take();
+        let result = if full {
+            status(&format!("⟳ Syncing everything to {destination}"));
+            session.sync(local, remote_path, options)
+        } else {
+            status(&format!(
+                "⟳ Pushing {} to {destination}",
+                count(paths.len())
+            ));
+            session.sync_paths(local, remote_path, &paths, options)
+        };
+
+        match result {
+            Ok(()) => {
+                pushes += 1;
+                let what = if full {
+                    "everything".to_string()
+                } else {
+                    count(paths.len())
+                };
+                status(&format!(
+                    "✓ Pushed {what} to {destination} (push #{pushes}), watching for changes"
+                ));
+                full = false;
+                retry_at = None;
+                retry_delay = RETRY_MIN;
+            }
+            Err(Error::Transfer { code }) if connection_lost(code) => {
+                status(&format!(
+                    "✗ Lost connection to {} (exit code {code}), retrying in {}s",
+                    session.alias(),
+                    retry_delay.as_secs()
+                ));
+                changes.restore(paths);
+                // A master that lost its connection can hang around until
+                // its keepalives time out. Drop it so the retry starts fresh.
+                session.close().ok();
+                retry_at = Some(Instant::now() + retry_delay);
+                retry_delay = (retry_delay * 2).min(RETRY_MAX);
+            }
+            Err(Error::Transfer { code }) => {
+                // Most likely a local file that can't be read. Trying again
+                // won't help until it changes again.
+                status(&format!(
+                    "✗ rsync failed with exit code {code}, watching for changes"
+                ));
+                full = false;
+                retry_at = None;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// rsync exit codes that mean the connection failed rather than the transfer.
+fn connection_lost(code: i32) -> bool {
+    // 10: socket I/O, 12: protocol stream, 30/35: timeouts, 255: ssh
+    matches!(code, 10 | 12 | 30 | 35 | 255)
+}
+
+fn count(n: usize) -> String {
+    if n == 1 {
+        "1 change".to_string()
+    } else {
+        format!("{n} changes")
+    }
+}
+
+/// Replaces the current terminal line.
+fn status(line: &str) {
+    let mut stdout = io::stdout();
+    write!(stdout, "\r\x1b[2K{line}").ok();
+    stdout.flush().ok();
+}
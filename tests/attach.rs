@@ -0,0 +1,107 @@
+mod common;
+
+use qs::attach::{attach_command, parse_sessions};
+use qs::config::{Config, Multiplexer};
+use qs::output::SessionRecord;
+use tempfile::TempDir;
+
+fn record(name: &str, multiplexer: Multiplexer, attached: bool) -> SessionRecord {
+    SessionRecord {
+        name: name.to_string(),
+        multiplexer,
+        attached,
+    }
+}
+
+#[test]
+fn attach_creates_the_session_if_needed() {
+    assert_eq!(
+        attach_command(Multiplexer::Tmux, "work"),
+        "tmux new-session -A -s work"
+    );
+    assert_eq!(
+        attach_command(Multiplexer::Screen, "my build"),
+        "screen -D -R -S 'my build'"
+    );
+}
+
+#[test]
+fn parse_tmux_sessions() {
+    assert_eq!(
+        parse_sessions(Multiplexer::Tmux, "0 qs\n2 long job\n"),
+        [
+            record("qs", Multiplexer::Tmux, false),
+            record("long job", Multiplexer::Tmux, true),
+        ]
+    );
+    assert!(parse_sessions(Multiplexer::Tmux, "").is_empty());
+}
+
+#[test]
+fn parse_screen_sessions() {
+    let output = "There are screens on:\n\
+                  \t4242.qs\t(Detached)\n\
+                  \t777.build.2\t(10/18/2026 09:12:01 AM)\t(Attached)\n\
+                  2 Sockets in /run/screen/S-deploy.\n";
+    assert_eq!(
+        parse_sessions(Multiplexer::Screen, output),
+        [
+            record("qs", Multiplexer::Screen, false),
+            record("build.2", Multiplexer::Screen, true),
+        ]
+    );
+    assert!(parse_sessions(Multiplexer::Screen, "No Sockets found in /run/screen.\n").is_empty());
+}
+
+/// A home where `web` keeps its shells in `multiplexer`, and ssh prints
+/// the remote command.
+fn setup(multiplexer: Option<Multiplexer>) -> TempDir {
+    let home = common::setup(r#"for last; do :; done; echo "$last""#);
+    let path = home.path().join(".config/qs/config.toml");
+    let mut config = Config::load_from(path.clone()).unwrap();
+    config.profiles.get_mut("web").unwrap().session = multiplexer;
+    config.save_to(path).unwrap();
+    home
+}
+
+fn remote_command(home: &TempDir, args: &[&str]) -> String {
+    let output = common::qs(home, args);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .trim_end()
+        .to_string()
+}
+
+#[test]
+fn connect_attaches_when_the_profile_sets_a_session() {
+    let home = setup(Some(Multiplexer::Screen));
+
+    assert_eq!(
+        remote_command(&home, &["connect", "web"]),
+        "screen -D -R -S qs"
+    );
+    assert_eq!(
+        remote_command(&home, &["connect", "web", "--session", "deploy"]),
+        "screen -D -R -S deploy"
+    );
+    // No remote command, just the target
+    assert_eq!(
+        remote_command(&home, &["connect", "web", "--no-session"]),
+        "deploy@10.0.0.5"
+    );
+}
+
+#[test]
+fn connect_session_defaults_to_tmux() {
+    let home = setup(None);
+
+    assert_eq!(
+        remote_command(&home, &["connect", "web"]),
+        "deploy@10.0.0.5"
+    );
+    assert_eq!(
+        remote_command(&home, &["connect", "web", "--session"]),
+        "tmux new-session -A -s qs"
+    );
+}
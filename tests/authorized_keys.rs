@@ -0,0 +1,176 @@
+use qs::keys::{install_key_script, key_blob, key_type_of, remove_key_script};
+use qs::util::{shell_quote, KeyType};
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+const OLD_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOldOldOld qs-tool";
+const NEW_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINewNewNew qs-tool";
+
+/// A temporary home directory standing in for the remote host, with its own
+/// sshd config.
+struct Remote {
+    home: TempDir,
+}
+
+impl Remote {
+    fn new(sshd_config: &str) -> Self {
+        let home = TempDir::new().unwrap();
+        fs::write(home.path().join("sshd_config"), sshd_config).unwrap();
+        Self { home }
+    }
+
+    fn sshd_config(&self) -> String {
+        self.home.path().join("sshd_config").display().to_string()
+    }
+
+    fn install(&self, pubkey: &str) -> (Option<i32>, String) {
+        self.run(&install_key_script(pubkey, &self.sshd_config()))
+    }
+
+    fn remove(&self, pubkey: &str) -> (Option<i32>, String) {
+        self.run(&remove_key_script(pubkey, &self.sshd_config()))
+    }
+
+    fn run(&self, script: &str) -> (Option<i32>, String) {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .env("HOME", self.home.path())
+            .output()
+            .unwrap();
+        (
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )
+    }
+
+    fn write_keys(&self, content: &str) {
+        fs::create_dir_all(self.home.path().join(".ssh")).unwrap();
+        fs::write(self.home.path().join(".ssh/authorized_keys"), content).unwrap();
+    }
+
+    fn keys(&self) -> String {
+        fs::read_to_string(self.home.path().join(".ssh/authorized_keys")).unwrap()
+    }
+}
+
+fn ok(output: &str) -> (Option<i32>, String) {
+    (Some(0), output.to_string())
+}
+
+#[test]
+fn shell_quote_escapes_single_quotes() {
+    assert_eq!(shell_quote("plain"), "'plain'");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+}
+
+#[test]
+fn key_blob_and_type_come_from_the_public_key() {
+    assert_eq!(
+        key_blob(OLD_KEY),
+        Some("AAAAC3NzaC1lZDI1NTE5AAAAIOldOldOld")
+    );
+    assert_eq!(key_type_of(OLD_KEY), Some(KeyType::Ed25519));
+    assert_eq!(key_type_of("ssh-rsa AAAAB3 x"), Some(KeyType::Rsa));
+    assert_eq!(
+        key_type_of("ecdsa-sha2-nistp521 AAAAE2 x"),
+        Some(KeyType::Ecdsa)
+    );
+    assert_eq!(key_type_of("garbage"), None);
+}
+
+#[test]
+fn install_is_idempotent() {
+    let remote = Remote::new("");
+
+    assert_eq!(remote.install(OLD_KEY), ok("added"));
+    assert_eq!(remote.install(OLD_KEY), ok("present"));
+    assert_eq!(remote.keys(), format!("{OLD_KEY}\n"));
+}
+
+#[test]
+fn install_handles_quotes_in_the_comment() {
+    let remote = Remote::new("");
+    let key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIQuote bob's \"laptop\" $HOME";
+
+    assert_eq!(remote.install(key), ok("added"));
+    assert_eq!(remote.keys(), format!("{key}\n"));
+}
+
+#[test]
+fn install_starts_a_new_line() {
+    let remote = Remote::new("");
+    remote.write_keys(OLD_KEY);
+
+    assert_eq!(remote.install(NEW_KEY), ok("added"));
+    assert_eq!(remote.keys(), format!("{OLD_KEY}\n{NEW_KEY}\n"));
+}
+
+#[test]
+fn install_honors_authorized_keys_file() {
+    let remote = Remote::new(
+        "# Comment\nPort 22\nAuthorizedKeysFile %h/.keys/%u_keys .ssh/authorized_keys\n",
+    );
+
+    assert_eq!(remote.install(OLD_KEY), ok("added"));
+
+    let user = String::from_utf8(Command::new("id").arg("-un").output().unwrap().stdout).unwrap();
+    let path = remote
+        .home
+        .path()
+        .join(".keys")
+        .join(format!("{}_keys", user.trim()));
+    assert_eq!(fs::read_to_string(path).unwrap(), format!("{OLD_KEY}\n"));
+    assert!(!remote.home.path().join(".ssh/authorized_keys").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn install_keeps_symlinked_authorized_keys() {
+    let remote = Remote::new("");
+    let real = remote.home.path().join("real_keys");
+    fs::write(&real, format!("{OLD_KEY}\n")).unwrap();
+    fs::create_dir(remote.home.path().join(".ssh")).unwrap();
+    std::os::unix::fs::symlink(&real, remote.home.path().join(".ssh/authorized_keys")).unwrap();
+
+    assert_eq!(remote.install(NEW_KEY), ok("added"));
+    assert!(
+        fs::symlink_metadata(remote.home.path().join(".ssh/authorized_keys"))
+            .unwrap()
+            .file_type()
+            .is_symlink()
+    );
+    assert_eq!(
+        fs::read_to_string(real).unwrap(),
+        format!("{OLD_KEY}\n{NEW_KEY}\n")
+    );
+}
+
+#[test]
+fn remove_drops_only_the_matching_key() {
+    let remote = Remote::new("");
+    remote.write_keys(&format!("{OLD_KEY}\n# keep me\n{NEW_KEY}\n"));
+
+    assert_eq!(remote.remove(OLD_KEY), ok("removed"));
+    assert_eq!(remote.keys(), format!("# keep me\n{NEW_KEY}\n"));
+
+    assert_eq!(remote.remove(OLD_KEY), ok("absent"));
+    assert_eq!(remote.keys(), format!("# keep me\n{NEW_KEY}\n"));
+}
+
+#[test]
+fn remove_handles_missing_authorized_keys() {
+    let remote = Remote::new("");
+
+    assert_eq!(remote.remove(OLD_KEY), ok("absent"));
+}
+
+#[test]
+fn remove_never_leaves_authorized_keys_without_keys() {
+    let remote = Remote::new("");
+    remote.write_keys(&format!("# only a comment\n{OLD_KEY}\n"));
+
+    assert_eq!(remote.remove(OLD_KEY).0, Some(3));
+    assert_eq!(remote.keys(), format!("# only a comment\n{OLD_KEY}\n"));
+}
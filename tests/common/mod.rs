@@ -0,0 +1,65 @@
+//! A fake home directory for running the `qs` binary against a stub `ssh`.
+
+use qs::config::{Config, Profile};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// Creates a home directory with the hosts `web` (default) and `db` in the
+/// group `all`, and an `ssh` on the PATH that runs `ssh_script` as a shell
+/// script.
+pub fn setup(ssh_script: &str) -> TempDir {
+    let home = TempDir::new().unwrap();
+
+    let mut profiles = HashMap::new();
+    for (alias, host) in [("web", "10.0.0.5"), ("db", "10.0.0.6")] {
+        profiles.insert(
+            alias.to_string(),
+            Profile {
+                host: host.to_string(),
+                user: "deploy".to_string(),
+                ..Default::default()
+            },
+        );
+    }
+    let mut groups = HashMap::new();
+    groups.insert("all".to_string(), vec!["web".to_string(), "db".to_string()]);
+    let config = Config {
+        default: Some("web".to_string()),
+        profiles,
+        groups,
+        ..Default::default()
+    };
+    config
+        .save_to(home.path().join(".config/qs/config.toml"))
+        .unwrap();
+
+    fs::create_dir(home.path().join("bin")).unwrap();
+    stub(&home, "ssh", ssh_script);
+
+    home
+}
+
+/// Puts a `program` on the PATH that runs `script` as a shell script.
+pub fn stub(home: &TempDir, program: &str, script: &str) {
+    let path = home.path().join("bin").join(program);
+    fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+pub fn qs(home: &TempDir, args: &[&str]) -> Output {
+    let path = format!(
+        "{}:{}",
+        home.path().join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    Command::new(env!("CARGO_BIN_EXE_qs"))
+        .args(args)
+        .env("HOME", home.path())
+        .env("PATH", path)
+        .output()
+        .unwrap()
+}
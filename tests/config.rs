@@ -1,4 +1,4 @@
-use qs::config::{Config, Profile};
+use qs::config::{Config, Multiplexer, Profile, Transfer};
 use std::{collections::HashMap, fs};
 use tempfile::TempDir;
 
@@ -7,18 +7,22 @@ fn get_profile_with_empty_config() {
     let config = Config {
         default: None,
         profiles: HashMap::new(),
+        ..Default::default()
     };
 
     let result = config.get_profile("default");
     assert!(result.is_err());
     assert_eq!(
-        result.unwrap_err(),
+        result.unwrap_err().to_string(),
         "No host found. Add a host: 'qs add <alias> --host <host> --user <user>'"
     );
 
     let result = config.get_profile("nonexistent");
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), "Alias 'nonexistent' doesn't exist");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Alias 'nonexistent' doesn't exist"
+    );
 }
 
 #[test]
@@ -30,18 +34,20 @@ fn get_profile_with_no_default_set() {
             host: "192.168.1.100".to_string(),
             user: "testuser".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     let config = Config {
         default: None,
         profiles,
+        ..Default::default()
     };
 
     let result = config.get_profile("default");
     assert!(result.is_err());
     assert_eq!(
-        result.unwrap_err(),
+        result.unwrap_err().to_string(),
         "No default set. Use 'qs set-default <alias>'"
     );
 
@@ -61,12 +67,14 @@ fn get_profile_with_valid_default() {
             host: "192.168.1.100".to_string(),
             user: "testuser".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     let config = Config {
         default: Some("server1".to_string()),
         profiles,
+        ..Default::default()
     };
 
     let result = config.get_profile("default");
@@ -85,17 +93,22 @@ fn get_profile_with_invalid_default() {
             host: "192.168.1.100".to_string(),
             user: "testuser".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     let config = Config {
         default: Some("nonexistent".to_string()),
         profiles,
+        ..Default::default()
     };
 
     let result = config.get_profile("default");
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), "Default alias 'nonexistent' not found");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Default alias 'nonexistent' not found"
+    );
 }
 
 #[test]
@@ -107,6 +120,7 @@ fn get_profile_direct_alias() {
             host: "192.168.1.100".to_string(),
             user: "user1".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
     profiles.insert(
@@ -115,12 +129,14 @@ fn get_profile_direct_alias() {
             host: "192.168.1.101".to_string(),
             user: "user2".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     let config = Config {
         default: Some("server1".to_string()),
         profiles,
+        ..Default::default()
     };
 
     let result = config.get_profile("server2");
@@ -131,7 +147,10 @@ fn get_profile_direct_alias() {
 
     let result = config.get_profile("server3");
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), "Alias 'server3' doesn't exist");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Alias 'server3' doesn't exist"
+    );
 }
 
 fn create_test_config() -> Config {
@@ -142,12 +161,14 @@ fn create_test_config() -> Config {
             host: "example.com".to_string(),
             user: "testuser".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     Config {
         default: Some("test".to_string()),
         profiles,
+        ..Default::default()
     }
 }
 
@@ -227,6 +248,7 @@ fn round_trip_with_multiple_profiles() {
             host: "192.168.1.100".to_string(),
             user: "admin".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
     profiles.insert(
@@ -235,12 +257,14 @@ fn round_trip_with_multiple_profiles() {
             host: "10.0.0.50".to_string(),
             user: "deploy".to_string(),
             port: 22,
+            ..Default::default()
         },
     );
 
     let config = Config {
         default: Some("server1".to_string()),
         profiles,
+        ..Default::default()
     };
 
     config.save_to(config_path.clone()).unwrap();
@@ -320,5 +344,112 @@ port = "invalid"
 
     // This should fail because "invalid" is not a valid u16
     assert!(result.is_err());
-    assert!(result.unwrap_err().contains("Invalid config file format"));
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid config file format"));
+}
+
+fn load(content: &str) -> Result<Config, qs::error::Error> {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(&config_path, content).unwrap();
+
+    Config::load_from(config_path)
+}
+
+#[test]
+fn load_config_with_transfer_method() {
+    let config = load(
+        r#"
+[profiles.nas]
+host = "10.0.0.9"
+user = "admin"
+transfer = "sftp"
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.profiles["nas"].transfer, Some(Transfer::Sftp));
+    assert_eq!(config.profiles["web"].transfer, None);
+
+    assert!(load("[profiles.nas]\nhost = \"h\"\nuser = \"u\"\ntransfer = \"ftp\"\n").is_err());
+}
+
+#[test]
+fn load_config_with_rsync_args() {
+    let config = load(
+        r#"
+[profiles.nas]
+host = "10.0.0.9"
+user = "admin"
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+rsync_args = ["--partial", "--bwlimit=5000"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.profiles["web"].rsync_args,
+        ["--partial", "--bwlimit=5000"]
+    );
+    assert!(config.profiles["nas"].rsync_args.is_empty());
+}
+
+#[test]
+fn load_config_with_workdir_and_env() {
+    let config = load(
+        r#"
+[profiles.nas]
+host = "10.0.0.9"
+user = "admin"
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+workdir = "/srv/app"
+
+[profiles.web.env]
+RAILS_ENV = "production"
+PORT = "8080"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.profiles["web"].workdir.as_deref(), Some("/srv/app"));
+    assert_eq!(config.profiles["web"].env["RAILS_ENV"], "production");
+    assert_eq!(config.profiles["web"].env["PORT"], "8080");
+    assert_eq!(config.profiles["nas"].workdir, None);
+    assert!(config.profiles["nas"].env.is_empty());
+}
+
+#[test]
+fn load_config_with_session() {
+    let config = load(
+        r#"
+[profiles.nas]
+host = "10.0.0.9"
+user = "admin"
+session = "screen"
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+session = "tmux"
+
+[profiles.db]
+host = "10.0.0.6"
+user = "deploy"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.profiles["web"].session, Some(Multiplexer::Tmux));
+    assert_eq!(config.profiles["nas"].session, Some(Multiplexer::Screen));
+    assert_eq!(config.profiles["db"].session, None);
+
+    assert!(load("[profiles.nas]\nhost = \"h\"\nuser = \"u\"\nsession = \"zellij\"\n").is_err());
 }
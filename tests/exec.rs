@@ -0,0 +1,231 @@
+mod common;
+
+use qs::util::{check_command, shell_join};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+/// An ssh that splits its last argument the way the remote shell would, and
+/// prints each resulting word in angle brackets.
+fn setup() -> TempDir {
+    common::setup(r#"eval "cmd=\${$#}"; eval "set -- $cmd"; printf '<%s>' "$@""#)
+}
+
+fn words(home: &TempDir, args: &[&str]) -> String {
+    let output = common::qs(home, args);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// What `sh` makes of a command line.
+fn split(line: &str) -> String {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("printf '<%s>' {line}"))
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn shell_join_keeps_argument_boundaries() {
+    for args in [
+        vec!["grep", "a b", "file"],
+        vec!["echo", "it's", "\"quoted\"", "back\\slash"],
+        vec!["echo", "$HOME", "`id`", "$(id)", "*", "a;b", "a|b", "a>b"],
+        vec!["printf", "%s\n", "", "  lead", "new\nline"],
+    ] {
+        let args: Vec<String> = args.into_iter().map(str::to_string).collect();
+        let expected: String = args.iter().map(|arg| format!("<{arg}>")).collect();
+        assert_eq!(split(&shell_join(&args)), expected, "{args:?}");
+    }
+}
+
+#[test]
+fn shell_join_only_quotes_what_needs_it() {
+    let args = ["ls", "-la", "/srv/app", "my file", "it's"].map(str::to_string);
+    assert_eq!(shell_join(&args), r"ls -la /srv/app 'my file' 'it'\''s'");
+}
+
+#[test]
+fn exec_quotes_each_argument() {
+    let home = setup();
+    assert_eq!(
+        words(&home, &["exec", "web", "--", "grep", "a b", "file"]),
+        "<grep><a b><file>"
+    );
+}
+
+#[test]
+fn raw_exec_leaves_the_command_to_the_remote_shell() {
+    let home = setup();
+    assert_eq!(
+        words(
+            &home,
+            &["exec", "web", "--raw", "--", "grep", "a b", "file"]
+        ),
+        "<grep><a><b><file>"
+    );
+    assert_eq!(
+        words(&home, &["exec", "web", "--raw", "--", "ls *.log"]),
+        "<ls><*.log>"
+    );
+}
+
+/// An ssh that prints its arguments to stderr and runs the remote command
+/// locally, with the same stdin.
+fn setup_local() -> TempDir {
+    common::setup(r#"echo "$@" >&2; eval "cmd=\${$#}"; eval "$cmd""#)
+}
+
+fn qs_with_input(home: &TempDir, args: &[&str], input: &[u8]) -> Output {
+    let path = format!(
+        "{}:{}",
+        home.path().join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let mut child = Command::new(env!("CARGO_BIN_EXE_qs"))
+        .args(args)
+        .env("HOME", home.path())
+        .env("PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn exec_forwards_stdin_without_a_terminal() {
+    let home = setup_local();
+    let input: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+    let output = qs_with_input(&home, &["exec", "web", "--", "cat"], &input);
+
+    assert!(output.status.success());
+    assert!(
+        output.stdout == input,
+        "stdin wasn't passed through unchanged"
+    );
+    // Piped stdin means no terminal, so binary data isn't mangled
+    let ssh_args = String::from_utf8_lossy(&output.stderr);
+    assert!(ssh_args.contains(" -T deploy@10.0.0.5 cat"), "{ssh_args}");
+}
+
+#[test]
+fn tty_flags_override_the_detection() {
+    let home = setup_local();
+
+    let output = qs_with_input(&home, &["exec", "web", "-t", "--", "true"], b"");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" -tt deploy@10.0.0.5 true"));
+
+    let output = qs_with_input(&home, &["exec", "web", "--no-tty", "--", "true"], b"");
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" -T deploy@10.0.0.5 true"));
+
+    let output = qs_with_input(&home, &["exec", "web", "-t", "--no-tty", "--", "true"], b"");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn tty_needs_a_single_host() {
+    let home = setup_local();
+    let output = qs_with_input(&home, &["exec", "web,db", "--tty", "--", "top"], b"");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("single host"));
+}
+
+/// Runs qs under a pseudo-terminal, the way it runs from an interactive
+/// shell.
+fn qs_in_terminal(home: &TempDir, args: &str) -> Output {
+    let path = format!(
+        "{}:{}",
+        home.path().join("bin").display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    Command::new("script")
+        .args(["-q", "-e", "-c"])
+        .arg(format!("{} {args}", env!("CARGO_BIN_EXE_qs")))
+        .arg("/dev/null")
+        .env("HOME", home.path())
+        .env("PATH", path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn groups_run_without_a_terminal_from_a_terminal() {
+    if !check_command("script") {
+        return;
+    }
+    let home = setup_local();
+
+    let output = qs_in_terminal(&home, "exec all -- true");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("✓ web: exit 0"), "{stdout}");
+    assert!(stdout.contains("✓ db: exit 0"), "{stdout}");
+
+    let output = qs_in_terminal(&home, "--output json exec all -- true");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("\"alias\": \"db\""), "{stdout}");
+
+    // A single host still gets one
+    let output = qs_in_terminal(&home, "exec web -- true");
+    assert!(String::from_utf8_lossy(&output.stdout).contains(" -tt deploy@10.0.0.5 true"));
+}
+
+#[test]
+fn exec_runs_in_the_given_directory_with_env() {
+    let home = setup_local();
+    let dir = home.path().join("srv dir");
+    std::fs::create_dir(&dir).unwrap();
+    let output = qs_with_input(
+        &home,
+        &[
+            "exec",
+            "web",
+            "--cd",
+            dir.to_str().unwrap(),
+            "--env",
+            "STAGE=it's prod",
+            "--",
+            "sh",
+            "-c",
+            "echo \"$PWD|$STAGE\"",
+        ],
+        b"",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}|it's prod\n", dir.display())
+    );
+}
+
+#[test]
+fn exec_stops_when_the_directory_is_missing() {
+    let home = setup_local();
+    let marker = home.path().join("ran");
+    let cmd = format!("test -d . || touch '{}'", marker.display());
+    let output = qs_with_input(
+        &home,
+        &[
+            "exec",
+            "web",
+            "--cd",
+            "/nonexistent/qs",
+            "--raw",
+            "--",
+            &cmd,
+        ],
+        b"",
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!marker.exists(), "the command ran after cd failed");
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use qs::config::Config;
+use qs::error::{Error, EXIT_ALIAS, EXIT_CONFIG, EXIT_KEY, EXIT_TRANSPORT};
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn qs(home: &TempDir, args: &[&str]) -> Option<i32> {
+    common::qs(home, args).status.code()
+}
+
+fn setup(ssh_exit: i32) -> TempDir {
+    common::setup(&format!("exit {ssh_exit}"))
+}
+
+#[test]
+fn error_kinds_map_to_exit_codes() {
+    assert_eq!(Error::Config("x".to_string()).exit_code(), EXIT_CONFIG);
+    assert_eq!(Error::Alias("x".to_string()).exit_code(), EXIT_ALIAS);
+    assert_eq!(Error::Transport("x".to_string()).exit_code(), 255);
+    assert_eq!(
+        Error::Remote {
+            code: 42,
+            message: String::new()
+        }
+        .exit_code(),
+        42
+    );
+}
+
+#[test]
+fn errors_keep_their_messages() {
+    let remote = Error::Remote {
+        code: 3,
+        message: String::new(),
+    };
+    assert_eq!(remote.to_string(), "Remote command exited with code 3");
+
+    let missing = Error::Dependency(vec!["rsync".to_string()]).to_string();
+    assert!(missing.starts_with("Missing required tools:\n\n  • rsync\n"));
+}
+
+#[test]
+fn library_errors_are_typed() {
+    let config = Config::default();
+    assert!(matches!(config.get_profile("nope"), Err(Error::Alias(_))));
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config.toml");
+    fs::write(&path, "not = [valid").unwrap();
+    assert!(matches!(Config::load_from(path), Err(Error::Config(_))));
+}
+
+#[test]
+fn rsync_is_optional() {
+    let home = setup(0);
+    common::stub(&home, "ssh-keygen", "exit 0");
+    // Only the stubs, so the host's own rsync can't be found
+    let qs_without_rsync = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_qs"))
+            .args(args)
+            .env("HOME", home.path())
+            .env("PATH", home.path().join("bin"))
+            .output()
+            .unwrap()
+    };
+
+    let output = qs_without_rsync(&["check"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("- rsync not found, files are copied with scp or sftp"));
+
+    let output = qs_without_rsync(&[
+        "add",
+        "api",
+        "--host",
+        "10.0.0.7",
+        "--user",
+        "u",
+        "--skip-key",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn init_reports_a_failed_key_generation() {
+    let home = setup(0);
+    common::stub(&home, "rsync", "exit 0");
+    common::stub(&home, "ssh-keygen", "exit 1");
+
+    assert_eq!(qs(&home, &["init"]), Some(EXIT_KEY));
+}
+
+#[test]
+fn exec_propagates_the_remote_exit_code() {
+    let home = setup(42);
+    assert_eq!(qs(&home, &["exec", "web", "--", "false"]), Some(42));
+
+    let home = setup(0);
+    assert_eq!(qs(&home, &["exec", "web", "--", "true"]), Some(0));
+}
+
+#[test]
+fn exec_on_several_hosts_exits_with_the_first_failure() {
+    let home = setup(255);
+    assert_eq!(
+        qs(&home, &["exec", "web,db", "--", "true"]),
+        Some(EXIT_TRANSPORT)
+    );
+}
+
+#[test]
+fn unknown_alias_exits_with_alias_code() {
+    let home = setup(0);
+    assert_eq!(qs(&home, &["exec", "nope", "--", "true"]), Some(EXIT_ALIAS));
+}
@@ -0,0 +1,101 @@
+use qs::config::{Config, Forward, ForwardKind};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn forward_display_uses_ssh_flags() {
+    let local = Forward {
+        kind: ForwardKind::Local,
+        spec: "5432:localhost:5432".to_string(),
+    };
+    let remote = Forward {
+        kind: ForwardKind::Remote,
+        spec: "8080:localhost:3000".to_string(),
+    };
+    let dynamic = Forward {
+        kind: ForwardKind::Dynamic,
+        spec: "1080".to_string(),
+    };
+
+    assert_eq!(local.to_string(), "-L 5432:localhost:5432");
+    assert_eq!(remote.to_string(), "-R 8080:localhost:3000");
+    assert_eq!(dynamic.to_string(), "-D 1080");
+}
+
+#[test]
+fn load_saved_forwards() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+
+    let content = r#"
+[profiles.db]
+host = "10.0.0.6"
+user = "admin"
+
+[profiles.db.forwards.postgres]
+type = "local"
+spec = "5432:localhost:5432"
+
+[profiles.db.forwards.socks]
+type = "dynamic"
+spec = "1080"
+"#;
+    fs::write(&config_path, content).unwrap();
+
+    let config = Config::load_from(config_path).unwrap();
+    let db = config.profiles.get("db").unwrap();
+
+    assert_eq!(db.forwards.len(), 2);
+    assert_eq!(db.forwards["postgres"].kind, ForwardKind::Local);
+    assert_eq!(db.forwards["postgres"].spec, "5432:localhost:5432");
+    assert_eq!(db.forwards["socks"].kind, ForwardKind::Dynamic);
+}
+
+#[test]
+fn saved_forwards_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+
+    let content = r#"
+[profiles.db]
+host = "10.0.0.6"
+user = "admin"
+
+[profiles.db.forwards.grafana]
+type = "remote"
+spec = "9000:localhost:3000"
+"#;
+    fs::write(&config_path, content).unwrap();
+
+    let config = Config::load_from(config_path.clone()).unwrap();
+    config.save_to(config_path.clone()).unwrap();
+    let reloaded = Config::load_from(config_path).unwrap();
+
+    assert_eq!(
+        reloaded.profiles.get("db").unwrap(),
+        config.profiles.get("db").unwrap()
+    );
+}
+
+#[test]
+fn invalid_forward_type_is_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+
+    let content = r#"
+[profiles.db]
+host = "10.0.0.6"
+user = "admin"
+
+[profiles.db.forwards.bad]
+type = "sideways"
+spec = "1"
+"#;
+    fs::write(&config_path, content).unwrap();
+
+    let result = Config::load_from(config_path);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid config file format"));
+}
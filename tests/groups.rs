@@ -0,0 +1,178 @@
+mod common;
+
+use qs::config::{Config, Profile};
+use qs::error::EXIT_ALIAS;
+use qs::util::{add_group, remove_alias, remove_group};
+use std::collections::HashMap;
+
+fn create_test_config() -> Config {
+    let mut profiles = HashMap::new();
+    for (alias, host) in [
+        ("web1", "10.0.0.1"),
+        ("web2", "10.0.0.2"),
+        ("db", "10.0.0.3"),
+    ] {
+        profiles.insert(
+            alias.to_string(),
+            Profile {
+                host: host.to_string(),
+                user: "admin".to_string(),
+                port: 22,
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut groups = HashMap::new();
+    groups.insert(
+        "web".to_string(),
+        vec!["web1".to_string(), "web2".to_string()],
+    );
+
+    Config {
+        default: Some("db".to_string()),
+        profiles,
+        groups,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn resolve_single_alias() {
+    let config = create_test_config();
+
+    assert_eq!(config.resolve_targets("web1").unwrap(), vec!["web1"]);
+    assert_eq!(config.resolve_targets("default").unwrap(), vec!["db"]);
+}
+
+#[test]
+fn resolve_group() {
+    let config = create_test_config();
+
+    assert_eq!(config.resolve_targets("web").unwrap(), vec!["web1", "web2"]);
+}
+
+#[test]
+fn resolve_comma_separated_list() {
+    let config = create_test_config();
+
+    assert_eq!(
+        config.resolve_targets("db, web2").unwrap(),
+        vec!["db", "web2"]
+    );
+
+    // Groups can be mixed in and duplicates are dropped
+    assert_eq!(
+        config.resolve_targets("web2,web,db").unwrap(),
+        vec!["web2", "web1", "db"]
+    );
+}
+
+#[test]
+fn resolve_unknown_targets() {
+    let config = create_test_config();
+
+    assert_eq!(
+        config.resolve_targets("web1,nope").unwrap_err().to_string(),
+        "Alias 'nope' doesn't exist"
+    );
+    assert_eq!(
+        config.resolve_targets("web1,").unwrap_err().to_string(),
+        "Invalid target 'web1,'"
+    );
+}
+
+#[test]
+fn add_group_validates_members() {
+    let mut config = create_test_config();
+
+    let result = add_group(
+        &mut config,
+        "all",
+        &["web1".to_string(), "ghost".to_string()],
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Alias 'ghost' doesn't exist"
+    );
+    assert!(!config.groups.contains_key("all"));
+
+    let result = add_group(&mut config, "db", &["web1".to_string()]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "'db' is already used as an alias"
+    );
+
+    let result = add_group(&mut config, "default", &["web1".to_string()]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "'default' is a reserved alias name"
+    );
+
+    let result = add_group(&mut config, "-all", &["web1".to_string()]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Group name cannot start with '-'"
+    );
+
+    let result = add_group(&mut config, "all", &[]);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "A group needs at least one alias"
+    );
+}
+
+#[test]
+fn add_and_remove_group() {
+    let mut config = create_test_config();
+
+    let members = vec!["db".to_string(), "web1".to_string(), "db".to_string()];
+    add_group(&mut config, "all", &members).unwrap();
+    assert_eq!(config.groups.get("all").unwrap(), &vec!["db", "web1"]);
+
+    remove_group(&mut config, "all").unwrap();
+    assert!(!config.groups.contains_key("all"));
+
+    assert_eq!(
+        remove_group(&mut config, "all").unwrap_err().to_string(),
+        "Group 'all' not found"
+    );
+}
+
+#[test]
+fn removing_alias_drops_it_from_groups() {
+    let mut config = create_test_config();
+
+    remove_alias(&mut config, "web1").unwrap();
+
+    assert_eq!(config.groups.get("web").unwrap(), &vec!["web2"]);
+    assert_eq!(config.resolve_targets("web").unwrap(), vec!["web2"]);
+}
+
+#[test]
+fn add_refuses_an_alias_named_like_a_group() {
+    let home = common::setup("exit 0");
+    // `add` checks for rsync before anything else
+    common::stub(&home, "rsync", "exit 0");
+
+    let output = common::qs(
+        &home,
+        &[
+            "add",
+            "all",
+            "--host",
+            "10.0.0.9",
+            "--user",
+            "deploy",
+            "--skip-key",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(EXIT_ALIAS));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: 'all' is already used as a group\n"
+    );
+
+    let config = Config::load_from(home.path().join(".config/qs/config.toml")).unwrap();
+    assert!(!config.profiles.contains_key("all"));
+}
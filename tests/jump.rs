@@ -0,0 +1,149 @@
+use qs::config::{Config, Profile};
+use qs::util::{rsync_ssh_command, ssh_args};
+use std::collections::HashMap;
+
+fn profile(host: &str, port: u16, jump: Option<&str>) -> Profile {
+    Profile {
+        host: host.to_string(),
+        user: "admin".to_string(),
+        port,
+        jump: jump.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+fn create_test_config() -> Config {
+    let mut profiles = HashMap::new();
+    profiles.insert("edge".to_string(), profile("edge.example.com", 22, None));
+    profiles.insert(
+        "bastion".to_string(),
+        profile("10.0.0.1", 2222, Some("edge")),
+    );
+    profiles.insert("app".to_string(), profile("10.0.1.5", 22, Some("bastion")));
+    profiles.insert(
+        "legacy".to_string(),
+        profile("10.0.2.5", 22, Some("ops@jump.example.com:2200")),
+    );
+
+    Config {
+        default: Some("app".to_string()),
+        profiles,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn no_jump_resolves_to_none() {
+    let config = create_test_config();
+    let edge = config.get_profile("edge").unwrap();
+
+    assert_eq!(config.resolve_jump(edge).unwrap(), None);
+    assert!(ssh_args(&config, edge).unwrap().is_empty());
+}
+
+#[test]
+fn literal_jump_is_used_as_is() {
+    let config = create_test_config();
+    let legacy = config.get_profile("legacy").unwrap();
+
+    assert_eq!(
+        config.resolve_jump(legacy).unwrap(),
+        Some("ops@jump.example.com:2200".to_string())
+    );
+}
+
+#[test]
+fn chained_jumps_resolve_outermost_first() {
+    let config = create_test_config();
+    let app = config.get_profile("app").unwrap();
+
+    assert_eq!(
+        config.resolve_jump(app).unwrap(),
+        Some("admin@edge.example.com:22,admin@10.0.0.1:2222".to_string())
+    );
+}
+
+#[test]
+fn jump_cycle_is_detected() {
+    let mut config = create_test_config();
+    config.profiles.get_mut("edge").unwrap().jump = Some("app".to_string());
+    let app = config.get_profile("app").unwrap();
+
+    assert_eq!(
+        config.resolve_jump(app).unwrap_err().to_string(),
+        "Jump host cycle detected: bastion → edge → app → bastion"
+    );
+}
+
+#[test]
+fn self_jump_is_detected() {
+    let mut config = create_test_config();
+    config.profiles.get_mut("edge").unwrap().jump = Some("edge".to_string());
+    let edge = config.get_profile("edge").unwrap();
+
+    assert_eq!(
+        config.resolve_jump(edge).unwrap_err().to_string(),
+        "Jump host cycle detected: edge → edge"
+    );
+}
+
+#[test]
+fn ssh_args_include_port_and_jump() {
+    let config = create_test_config();
+    let bastion = config.get_profile("bastion").unwrap();
+
+    assert_eq!(
+        ssh_args(&config, bastion).unwrap(),
+        vec!["-p", "2222", "-J", "admin@edge.example.com:22"]
+    );
+}
+
+#[test]
+fn rsync_ssh_command_honors_jump() {
+    let config = create_test_config();
+    let app = config.get_profile("app").unwrap();
+
+    let command = rsync_ssh_command(&config, app).unwrap();
+    assert!(command.starts_with("ssh -J admin@edge.example.com:22,admin@10.0.0.1:2222 "));
+    assert!(command.contains("ControlMaster=auto"));
+}
+
+#[test]
+fn keyed_bastion_is_reached_through_a_proxy_command() {
+    let mut config = create_test_config();
+    let bastion = config.profiles.get_mut("bastion").unwrap();
+    bastion.identity_file = Some("/keys/bastion".to_string());
+    let app = config.get_profile("app").unwrap();
+
+    assert_eq!(
+        ssh_args(&config, app).unwrap(),
+        vec![
+            "-o",
+            "ProxyCommand=ssh -p 2222 -i /keys/bastion -o IdentitiesOnly=yes \
+             -J admin@edge.example.com:22 admin@10.0.0.1 -W %h:%p",
+        ]
+    );
+
+    let command = rsync_ssh_command(&config, app).unwrap();
+    assert!(command.starts_with(
+        "ssh -o 'ProxyCommand=ssh -p 2222 -i /keys/bastion -o IdentitiesOnly=yes \
+         -J admin@edge.example.com:22 admin@10.0.0.1 -W %h:%p' "
+    ));
+}
+
+#[test]
+fn keyed_outer_hop_switches_the_chain_to_proxy_commands() {
+    let mut config = create_test_config();
+    let edge = config.profiles.get_mut("edge").unwrap();
+    edge.identity_file = Some("/keys/edge".to_string());
+    let app = config.get_profile("app").unwrap();
+
+    assert_eq!(
+        ssh_args(&config, app).unwrap(),
+        vec![
+            "-o",
+            "ProxyCommand=ssh -p 2222 -o 'ProxyCommand=ssh -i /keys/edge -o \
+             IdentitiesOnly=yes admin@edge.example.com -W %h:%p' admin@10.0.0.1 -W %h:%p",
+        ]
+    );
+}
@@ -0,0 +1,284 @@
+mod common;
+
+use qs::config::{Config, Profile};
+use qs::error::EXIT_FAILURE;
+use qs::util::{check_command, ensure_key, key_path, profile_key_path, ssh_args, KeyType};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+#[test]
+fn keygen_args_per_key_type() {
+    assert_eq!(KeyType::Ed25519.keygen_args(), vec!["-t", "ed25519"]);
+    assert_eq!(KeyType::Rsa.keygen_args(), vec!["-t", "rsa", "-b", "4096"]);
+    assert_eq!(
+        KeyType::Ecdsa.keygen_args(),
+        vec!["-t", "ecdsa", "-b", "521"]
+    );
+
+    assert_eq!(KeyType::Rsa.default_file_name(), "id_rsa");
+}
+
+#[test]
+fn key_names_resolve_inside_ssh_dir() {
+    let home = dirs::home_dir().unwrap();
+
+    assert_eq!(key_path("id_work"), home.join(".ssh").join("id_work"));
+    assert_eq!(key_path("~/keys/legacy"), home.join("keys").join("legacy"));
+    assert_eq!(key_path("/etc/keys/ci"), PathBuf::from("/etc/keys/ci"));
+}
+
+#[test]
+fn profile_key_defaults_to_ed25519() {
+    let mut profile = Profile {
+        host: "10.0.0.5".to_string(),
+        user: "deploy".to_string(),
+        ..Default::default()
+    };
+    let home = dirs::home_dir().unwrap();
+
+    assert_eq!(
+        profile_key_path(&profile),
+        home.join(".ssh").join("id_ed25519")
+    );
+
+    profile.identity_file = Some("~/.ssh/id_legacy".to_string());
+    assert_eq!(
+        profile_key_path(&profile),
+        home.join(".ssh").join("id_legacy")
+    );
+}
+
+#[test]
+fn identity_file_is_passed_with_identities_only() {
+    let profile = Profile {
+        host: "10.0.0.5".to_string(),
+        user: "deploy".to_string(),
+        identity_file: Some("/keys/deploy".to_string()),
+        ..Default::default()
+    };
+    let config = Config::default();
+
+    assert_eq!(
+        ssh_args(&config, &profile).unwrap(),
+        vec!["-i", "/keys/deploy", "-o", "IdentitiesOnly=yes"]
+    );
+}
+
+#[test]
+fn ensure_key_generates_requested_type_once() {
+    if !check_command("ssh-keygen") {
+        return;
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("nested").join("id_test");
+
+    assert_eq!(ensure_key(&path, KeyType::Ecdsa).unwrap(), path);
+    let public_key = fs::read_to_string(path.with_extension("pub")).unwrap();
+    assert!(public_key.starts_with("ecdsa-sha2-nistp521 "));
+    assert!(public_key.trim_end().ends_with(" qs-tool"));
+
+    // An existing key is reused rather than regenerated
+    ensure_key(&path, KeyType::Ed25519).unwrap();
+    assert_eq!(
+        fs::read_to_string(path.with_extension("pub")).unwrap(),
+        public_key
+    );
+}
+
+/// A home with `web` and `db` sharing `~/.ssh/id_ed25519`, and an ssh that
+/// logs every call. It installs and removes keys as asked, except that it
+/// fails `fail_on`, some part of its command line.
+fn rotation_setup(fail_on: &str) -> TempDir {
+    let home = common::setup(&format!(
+        r#"printf '%s\n' "$*" >> "$HOME/ssh.log"
+case "$*" in *{fail_on}*) echo "{fail_on} refused" >&2; exit 255 ;; esac
+eval "script=\${{$#}}"
+case "$script" in
+  *"echo added"*) echo added ;;
+  *"echo removed"*) echo removed ;;
+esac"#
+    ));
+    common::stub(
+        &home,
+        "ssh-keygen",
+        r#"echo "$*" >> "$HOME/keygen.log"
+while [ $# -gt 0 ]; do if [ "$1" = -f ]; then f=$2; fi; shift; done
+echo new > "$f"
+echo "ssh-ed25519 AAAAnew qs-tool" > "$f.pub""#,
+    );
+
+    let ssh_dir = home.path().join(".ssh");
+    fs::create_dir(&ssh_dir).unwrap();
+    fs::write(ssh_dir.join("id_ed25519"), "old").unwrap();
+    fs::write(
+        ssh_dir.join("id_ed25519.pub"),
+        "ssh-ed25519 AAAAold qs-tool\n",
+    )
+    .unwrap();
+
+    home
+}
+
+fn read(home: &TempDir, name: &str) -> String {
+    fs::read_to_string(home.path().join(name)).unwrap_or_default()
+}
+
+fn key_files(home: &TempDir) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(home.path().join(".ssh"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("id_"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn calls_with<'a>(log: &'a str, needle: &str) -> Vec<&'a str> {
+    log.lines().filter(|line| line.contains(needle)).collect()
+}
+
+#[test]
+fn rotation_retires_the_old_key_once_every_host_accepts_the_new_one() {
+    let home = rotation_setup("nothing-fails");
+
+    let output = common::qs(&home, &["keys", "rotate", "--yes"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("  ✓ db\n  ✓ web\n"), "{stdout}");
+
+    // The new key took the old one's place, which is kept aside
+    assert_eq!(
+        key_files(&home),
+        [
+            "id_ed25519",
+            "id_ed25519.old",
+            "id_ed25519.old.pub",
+            "id_ed25519.pub"
+        ]
+    );
+    assert_eq!(
+        read(&home, ".ssh/id_ed25519.pub"),
+        "ssh-ed25519 AAAAnew qs-tool\n"
+    );
+    assert_eq!(read(&home, ".ssh/id_ed25519.old"), "old");
+
+    // Installed over the master connection, verified and the old key removed
+    // with the new key alone
+    let log = read(&home, "ssh.log");
+    let new_key = home.path().join(".ssh/id_ed25519.new");
+    let with_new_key = format!("-i {}", new_key.display());
+    assert_eq!(calls_with(&log, "AAAAnew").len(), 2);
+    assert!(calls_with(&log, "AAAAnew")
+        .iter()
+        .all(|call| call.contains("ControlMaster=auto") && !call.contains(&with_new_key)));
+    for host in ["10.0.0.5", "10.0.0.6"] {
+        let calls = calls_with(&log, host);
+        assert!(calls[1].contains(&with_new_key) && calls[1].ends_with(" true"));
+        assert!(calls[2].contains(&with_new_key) && calls[2].contains("AAAAold"));
+    }
+}
+
+#[test]
+fn partial_rotation_keeps_the_old_key_and_the_new_one_for_later() {
+    let home = rotation_setup("10.0.0.6");
+
+    let output = common::qs(&home, &["keys", "rotate", "--yes"]);
+    assert_eq!(output.status.code(), Some(EXIT_FAILURE));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("  ✗ db: install failed: 10.0.0.6 refused"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("  ✓ web"), "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Partial rotation: 1/2 host(s)"));
+
+    // Nothing renamed, and the old key was removed nowhere
+    assert_eq!(
+        key_files(&home),
+        [
+            "id_ed25519",
+            "id_ed25519.new",
+            "id_ed25519.new.pub",
+            "id_ed25519.pub"
+        ]
+    );
+    assert_eq!(read(&home, ".ssh/id_ed25519"), "old");
+    assert!(calls_with(&read(&home, "ssh.log"), "AAAAold").is_empty());
+}
+
+#[test]
+fn rerunning_a_rotation_reuses_the_new_key() {
+    let home = rotation_setup("nothing-fails");
+    let ssh_dir = home.path().join(".ssh");
+    fs::write(ssh_dir.join("id_ed25519.new"), "left over").unwrap();
+    fs::write(
+        ssh_dir.join("id_ed25519.new.pub"),
+        "ssh-ed25519 AAAAleft qs-tool\n",
+    )
+    .unwrap();
+
+    let output = common::qs(&home, &["keys", "rotate", "--yes"]);
+    assert!(output.status.success());
+
+    assert_eq!(read(&home, "keygen.log"), "");
+    assert_eq!(read(&home, ".ssh/id_ed25519"), "left over");
+    assert_eq!(
+        calls_with(&read(&home, "ssh.log"), "AAAAleft").len(),
+        2,
+        "installed on both hosts"
+    );
+}
+
+#[test]
+fn rotation_reports_hosts_where_the_old_key_stays() {
+    // Only the removal of the old key fails
+    let home = rotation_setup("AAAAold");
+
+    let output = common::qs(&home, &["keys", "rotate", "--yes"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("  ⚠ web: new key installed, old key not removed (AAAAold refused)"),
+        "{stdout}"
+    );
+
+    // Every host accepts the new key, so it is in use either way
+    assert_eq!(
+        read(&home, ".ssh/id_ed25519.pub"),
+        "ssh-ed25519 AAAAnew qs-tool\n"
+    );
+    assert!(home.path().join(".ssh/id_ed25519.old").exists());
+}
+
+#[test]
+fn add_skips_the_key_copy_without_a_public_key() {
+    let home = common::setup("exit 0");
+    common::stub(&home, "rsync", "exit 0");
+    let key = home.path().join("legacy.pem");
+    fs::write(&key, "private only").unwrap();
+
+    let output = common::qs(
+        &home,
+        &[
+            "add",
+            "old",
+            "--host",
+            "10.0.0.8",
+            "--user",
+            "admin",
+            "--key",
+            key.to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("⚠ Failed to copy SSH key: Failed to read"),
+        "{stderr}"
+    );
+
+    let config = Config::load_from(home.path().join(".config/qs/config.toml")).unwrap();
+    assert!(config.profiles.contains_key("old"));
+}
@@ -0,0 +1,178 @@
+#![cfg(feature = "native-ssh")]
+
+use qs::config::{Config, Profile};
+use qs::error::Error;
+use qs::native::Connection;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn profile(port: u16, jump: Option<&str>) -> Profile {
+    Profile {
+        host: "127.0.0.1".to_string(),
+        user: whoami(),
+        port,
+        jump: jump.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+fn whoami() -> String {
+    let output = Command::new("id").arg("-un").output().unwrap();
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn keygen(path: &Path) {
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn find_sshd() -> Option<PathBuf> {
+    ["/usr/sbin/sshd", "/usr/local/sbin/sshd", "/sbin/sshd"]
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+}
+
+/// An sshd on a free local port that accepts a freshly generated client key.
+struct Sshd {
+    child: Child,
+    port: u16,
+    key: PathBuf,
+}
+
+impl Sshd {
+    fn spawn(sshd: &Path, dir: &Path) -> Self {
+        let host_key = dir.join("host_key");
+        let key = dir.join("client_key");
+        keygen(&host_key);
+        keygen(&key);
+        fs::copy(dir.join("client_key.pub"), dir.join("authorized_keys")).unwrap();
+
+        let port = free_port();
+        let config = dir.join("sshd_config");
+        fs::write(
+            &config,
+            format!(
+                "Port {port}\nListenAddress 127.0.0.1\nHostKey {}\nAuthorizedKeysFile {}\n\
+                 PidFile {}\nStrictModes no\nUsePAM no\nPasswordAuthentication no\n\
+                 Subsystem sftp internal-sftp\n",
+                host_key.display(),
+                dir.join("authorized_keys").display(),
+                dir.join("sshd.pid").display(),
+            ),
+        )
+        .unwrap();
+
+        let child = Command::new(sshd)
+            .args(["-D", "-e", "-f"])
+            .arg(&config)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // Wait for it to listen
+        for _ in 0..50 {
+            if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        Self { child, port, key }
+    }
+}
+
+impl Drop for Sshd {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+#[test]
+fn jump_hosts_are_refused() {
+    let result = Connection::open(&Config::default(), &profile(22, Some("bastion")));
+    assert!(matches!(result, Err(Error::Transport(msg)) if msg.contains("Jump hosts")));
+}
+
+#[test]
+fn unreachable_hosts_are_transport_errors() {
+    let result = Connection::open(&Config::default(), &profile(free_port(), None));
+    assert!(matches!(result, Err(Error::Transport(_))));
+}
+
+/// Runs against a real sshd, so it is skipped where none is installed.
+#[test]
+fn exec_and_sftp_against_local_sshd() {
+    let Some(sshd) = find_sshd() else {
+        eprintln!("sshd not found, skipping");
+        return;
+    };
+
+    let home = TempDir::new().unwrap();
+    // known_hosts is written under $HOME; no other test here reads it
+    std::env::set_var("HOME", home.path());
+
+    let server = Sshd::spawn(&sshd, home.path());
+    let mut profile = profile(server.port, None);
+    profile.identity_file = Some(server.key.display().to_string());
+    let conn = Connection::open(&Config::default(), &profile).unwrap();
+
+    let output = conn.exec("echo hello; echo oops >&2; exit 7").unwrap();
+    assert_eq!(output.stdout, b"hello\n");
+    assert_eq!(output.stderr, b"oops\n");
+    assert_eq!(output.status.code(), Some(7));
+
+    // More stderr than a channel window holds must not stall the command
+    let output = conn
+        .exec("head -c 4000000 /dev/zero >&2; echo done")
+        .unwrap();
+    assert_eq!(output.stderr.len(), 4_000_000);
+    assert_eq!(output.stdout, b"done\n");
+    assert!(fs::read_to_string(home.path().join(".ssh/known_hosts"))
+        .unwrap()
+        .contains(&format!("[127.0.0.1]:{}", server.port)));
+
+    let local = home.path().join("local");
+    fs::create_dir_all(local.join("sub")).unwrap();
+    fs::write(local.join("sub/file.txt"), "payload").unwrap();
+    let remote = home.path().join("remote");
+    fs::create_dir(&remote).unwrap();
+
+    conn.send(&local, &remote.display().to_string(), false)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(remote.join("local/sub/file.txt")).unwrap(),
+        "payload"
+    );
+
+    let back = home.path().join("back");
+    fs::create_dir(&back).unwrap();
+    conn.get(
+        &remote.join("local/sub/file.txt").display().to_string(),
+        &back,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(back.join("file.txt")).unwrap(),
+        "payload"
+    );
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{qs, setup};
+use qs::output::{render, DataFormat, OutputFormat};
+use serde_json::Value;
+
+fn json(args: &[&str], ssh_script: &str) -> (Value, Option<i32>) {
+    let home = setup(ssh_script);
+    let output = qs(&home, args);
+    (
+        serde_json::from_slice(&output.stdout).unwrap(),
+        output.status.code(),
+    )
+}
+
+#[test]
+fn only_json_and_yaml_are_rendered() {
+    assert_eq!(OutputFormat::Table.data(), None);
+    assert_eq!(OutputFormat::Json.data(), Some(DataFormat::Json));
+
+    assert_eq!(render(&["a"], DataFormat::Json), "[\n  \"a\"\n]\n");
+    assert_eq!(render(&["a"], DataFormat::Yaml), "- a\n");
+}
+
+#[test]
+fn list_emits_full_profiles() {
+    let (hosts, code) = json(&["list", "--output", "json"], "exit 0");
+
+    assert_eq!(code, Some(0));
+    assert_eq!(hosts[0]["alias"], "db");
+    assert_eq!(hosts[0]["default"], false);
+    assert_eq!(hosts[1]["alias"], "web");
+    assert_eq!(hosts[1]["default"], true);
+    assert_eq!(hosts[1]["host"], "10.0.0.5");
+    assert_eq!(hosts[1]["user"], "deploy");
+    assert_eq!(hosts[1]["port"], 22);
+}
+
+#[test]
+fn list_as_yaml() {
+    let home = setup("exit 0");
+    let output = qs(&home, &["--output", "yaml", "list"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.starts_with("- alias: db\n  default: false\n  host: 10.0.0.6\n"));
+}
+
+#[test]
+fn status_reports_master_state() {
+    // `ssh -O check` fails when there is no master
+    let (status, _) = json(&["status", "--output", "json"], "exit 255");
+    assert_eq!(status["alias"], "web");
+    assert_eq!(status["multiplex"], true);
+    assert_eq!(status["active"], false);
+    assert_eq!(status["pid"], Value::Null);
+
+    let (status, _) = json(
+        &["status", "db", "--output", "json"],
+        "echo 'Master running (pid=4242)' >&2",
+    );
+    assert_eq!(status["alias"], "db");
+    assert_eq!(status["active"], true);
+    assert_eq!(status["pid"], 4242);
+}
+
+#[test]
+fn exec_emits_a_record_per_host() {
+    let (records, code) = json(
+        &["exec", "db,web", "--output", "json", "--", "uptime"],
+        r#"for last; do :; done; echo "ran $last"; echo warning >&2; exit 7"#,
+    );
+
+    assert_eq!(code, Some(7));
+    let records = records.as_array().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["alias"], "db");
+    assert_eq!(records[1]["alias"], "web");
+    for record in records {
+        assert_eq!(record["stdout"], "ran uptime\n");
+        assert_eq!(record["stderr"], "warning\n");
+        assert_eq!(record["exit_code"], 7);
+        assert!(record["duration_ms"].is_u64());
+    }
+}
@@ -14,15 +14,20 @@ fn remove_non_existent_alias() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "nonexistent");
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), "Alias 'nonexistent' not found");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Alias 'nonexistent' not found"
+    );
     assert_eq!(config.profiles.len(), 1);
     assert_eq!(config.default, Some("server1".to_string()));
 }
@@ -39,6 +44,7 @@ fn remove_non_default_alias() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -47,10 +53,12 @@ fn remove_non_default_alias() {
                     host: "192.168.1.2".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "server2");
@@ -72,10 +80,12 @@ fn remove_default_with_no_aliases_remaining() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "server1");
@@ -103,6 +113,7 @@ fn remove_default_with_one_alias_remaining() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -111,10 +122,12 @@ fn remove_default_with_one_alias_remaining() {
                     host: "192.168.1.2".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "server1");
@@ -143,6 +156,7 @@ fn remove_default_with_multiple_aliases_remaining() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -151,6 +165,7 @@ fn remove_default_with_multiple_aliases_remaining() {
                     host: "192.168.1.2".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -159,10 +174,12 @@ fn remove_default_with_multiple_aliases_remaining() {
                     host: "192.168.1.3".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "server1");
@@ -192,6 +209,7 @@ fn remove_sequence_leaves_one_default() {
                     host: "192.168.1.1".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -200,6 +218,7 @@ fn remove_sequence_leaves_one_default() {
                     host: "192.168.1.2".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles.insert(
@@ -208,10 +227,12 @@ fn remove_sequence_leaves_one_default() {
                     host: "192.168.1.3".to_string(),
                     user: "admin".to_string(),
                     port: 22,
+                    ..Default::default()
                 },
             );
             profiles
         },
+        ..Default::default()
     };
 
     let result = remove_alias(&mut config, "server3");
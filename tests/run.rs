@@ -0,0 +1,149 @@
+mod common;
+
+use qs::script::{interpreter, remote_command};
+use qs::util::parse_env;
+use std::fs;
+use tempfile::TempDir;
+
+fn words(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+/// An ssh that runs the remote command locally, with the same stdin.
+fn setup(script: &str) -> TempDir {
+    let home = common::setup(r#"eval "cmd=\${$#}"; eval "$cmd""#);
+    fs::write(home.path().join("task.sh"), script).unwrap();
+    home
+}
+
+#[test]
+fn interpreter_comes_from_the_shebang() {
+    assert_eq!(
+        interpreter(b"#!/bin/bash -e\necho hi\n"),
+        ["/bin/bash", "-e"]
+    );
+    assert_eq!(
+        interpreter(b"#! /usr/bin/env python3\nprint(1)\n"),
+        ["/usr/bin/env", "python3"]
+    );
+    assert_eq!(interpreter(b"echo no shebang\n"), ["sh"]);
+    assert_eq!(interpreter(b"#!\n"), ["sh"]);
+    assert_eq!(interpreter(b""), ["sh"]);
+}
+
+#[test]
+fn shells_read_the_script_with_dash_s() {
+    assert_eq!(
+        remote_command(&words(&["/bin/bash", "-e"]), &[], &words(&["a b", "-v"])),
+        "/bin/bash -e -s -- 'a b' -v"
+    );
+    assert_eq!(
+        remote_command(&words(&["/usr/bin/env", "zsh"]), &[], &[]),
+        "/usr/bin/env zsh -s --"
+    );
+}
+
+#[test]
+fn other_interpreters_read_the_script_from_dash() {
+    assert_eq!(
+        remote_command(&words(&["/usr/bin/env", "python3"]), &[], &words(&["x"])),
+        "/usr/bin/env python3 - x"
+    );
+    assert_eq!(
+        remote_command(&words(&["perl", "-w"]), &[], &[]),
+        "perl -w -"
+    );
+}
+
+#[test]
+fn env_is_set_with_env() {
+    let env = [
+        ("STAGE".to_string(), "prod".to_string()),
+        ("MSG".to_string(), "it's up".to_string()),
+    ];
+    assert_eq!(
+        remote_command(&words(&["sh"]), &env, &[]),
+        r"env STAGE=prod 'MSG=it'\''s up' sh -s --"
+    );
+}
+
+#[test]
+fn parse_env_needs_a_variable_name() {
+    assert_eq!(
+        parse_env("A_1=x=y"),
+        Ok(("A_1".to_string(), "x=y".to_string()))
+    );
+    assert_eq!(
+        parse_env("EMPTY="),
+        Ok(("EMPTY".to_string(), String::new()))
+    );
+    assert!(parse_env("NOVALUE").is_err());
+    assert!(parse_env("=x").is_err());
+    assert!(parse_env("1X=x").is_err());
+    assert!(parse_env("A-B=x").is_err());
+}
+
+#[test]
+fn run_streams_the_script_with_args_and_env() {
+    let home = setup("echo \"$GREETING $1|$2\"\nexit 3\n");
+    let script = home.path().join("task.sh");
+    let output = common::qs(
+        &home,
+        &[
+            "run",
+            "--env",
+            "GREETING=hello there",
+            "web",
+            script.to_str().unwrap(),
+            "a b",
+            "--flag",
+        ],
+    );
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello there a b|--flag\n"
+    );
+}
+
+#[test]
+fn run_defaults_to_the_default_host() {
+    let home = setup("#!/bin/sh\necho \"ran $1\"\n");
+    let script = home.path().join("task.sh");
+    let output = common::qs(&home, &["run", script.to_str().unwrap(), "web"]);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran web\n");
+}
+
+#[test]
+fn run_on_several_hosts_gives_each_the_whole_script() {
+    let home = setup("echo one\necho two\nexit 4\n");
+    let script = home.path().join("task.sh");
+    let output = common::qs(&home, &["run", "web,db", script.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for alias in ["web", "db "] {
+        assert!(stdout.contains(&format!("{alias} | one")), "{stdout}");
+        assert!(stdout.contains(&format!("{alias} | two")), "{stdout}");
+    }
+}
+
+#[test]
+fn run_on_one_host_captures_the_script_as_json() {
+    let home = setup("echo one\necho two >&2\nexit 5\n");
+    let script = home.path().join("task.sh");
+    let output = common::qs(
+        &home,
+        &["--output", "json", "run", "web", script.to_str().unwrap()],
+    );
+
+    assert_eq!(output.status.code(), Some(5));
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(records[0]["alias"], "web");
+    assert_eq!(records[0]["stdout"], "one\n");
+    assert_eq!(records[0]["stderr"], "two\n");
+    assert_eq!(records[0]["exit_code"], 5);
+}
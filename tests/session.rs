@@ -0,0 +1,125 @@
+use qs::config::{Config, ConnectionSettings, Profile};
+use qs::error::Error;
+use qs::session::{MasterStatus, Session};
+use std::collections::HashMap;
+
+fn profile(host: &str, jump: Option<&str>) -> Profile {
+    Profile {
+        host: host.to_string(),
+        user: "admin".to_string(),
+        jump: jump.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+// Multiplexing is off so building commands doesn't create a socket directory
+fn create_test_config() -> Config {
+    let mut profiles = HashMap::new();
+    profiles.insert("bastion".to_string(), profile("10.0.0.1", None));
+    profiles.insert("app".to_string(), profile("10.0.1.5", Some("bastion")));
+
+    Config {
+        default: Some("app".to_string()),
+        profiles,
+        settings: ConnectionSettings {
+            multiplex: Some(false),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn args(session: &Session, remote_cmd: Option<&str>) -> Vec<String> {
+    session
+        .command(remote_cmd)
+        .unwrap()
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn from_config_resolves_the_default_alias() {
+    let config = create_test_config();
+    let session = Session::from_config(&config, "default").unwrap();
+
+    assert_eq!(session.alias(), "app");
+    assert_eq!(session.profile().host, "10.0.1.5");
+}
+
+#[test]
+fn from_config_rejects_unknown_aliases() {
+    let config = create_test_config();
+    assert!(matches!(
+        Session::from_config(&config, "nope"),
+        Err(Error::Alias(_))
+    ));
+}
+
+#[test]
+fn from_config_rejects_broken_jump_chains() {
+    let mut config = create_test_config();
+    config
+        .profiles
+        .insert("loop".to_string(), profile("10.0.9.9", Some("loop")));
+
+    assert!(matches!(
+        Session::from_config(&config, "loop"),
+        Err(Error::Config(_))
+    ));
+}
+
+#[test]
+fn command_resolves_jump_hosts_and_appends_the_remote_command() {
+    let config = create_test_config();
+    let session = Session::from_config(&config, "app").unwrap();
+
+    assert_eq!(
+        args(&session, Some("uptime")),
+        [
+            "-o",
+            "ControlMaster=no",
+            "-o",
+            "ControlPath=none",
+            "-J",
+            "admin@10.0.0.1:22",
+            "admin@10.0.1.5",
+            "uptime",
+        ]
+    );
+}
+
+#[test]
+fn command_without_remote_command_opens_a_shell() {
+    let config = create_test_config();
+    let session = Session::from_config(&config, "bastion").unwrap();
+
+    assert_eq!(args(&session, None).last().unwrap(), "admin@10.0.0.1");
+}
+
+#[test]
+fn standalone_session_uses_jump_literally() {
+    let mut standalone = profile("10.0.3.3", Some("ops@jump.example.com:2200"));
+    standalone.settings.multiplex = Some(false);
+    let session = Session::new("standalone", standalone);
+
+    let args = args(&session, Some("true"));
+    assert!(args
+        .windows(2)
+        .any(|pair| pair == ["-J", "ops@jump.example.com:2200"]));
+}
+
+#[test]
+fn status_is_disabled_without_multiplexing() {
+    let config = create_test_config();
+    let session = Session::from_config(&config, "app").unwrap();
+
+    assert_eq!(session.status().unwrap(), MasterStatus::Disabled);
+}
+
+#[test]
+fn transfer_errors_exit_with_the_program_code() {
+    let err = Error::Transfer { code: 23 };
+    assert_eq!(err.exit_code(), 23);
+    assert_eq!(err.to_string(), "Transfer failed with exit code 23");
+}
@@ -0,0 +1,161 @@
+use qs::config::Config;
+use qs::util::{rsync_ssh_command, setup_multiplex, socket_dir, ssh_args};
+use std::fs;
+use tempfile::TempDir;
+
+fn load(content: &str) -> Config {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(&config_path, content).unwrap();
+
+    Config::load_from(config_path).unwrap()
+}
+
+#[test]
+fn defaults_when_nothing_is_set() {
+    let config = load(
+        r#"
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+"#,
+    );
+    let web = config.get_profile("web").unwrap();
+    let settings = config.settings_for(web);
+
+    assert!(settings.multiplex());
+    assert_eq!(settings.control_persist(), "10m");
+    assert!(socket_dir(&settings).ends_with(".ssh/sockets"));
+    assert!(ssh_args(&config, web).unwrap().is_empty());
+
+    let mux = setup_multiplex(&settings);
+    assert_eq!(mux[1], "ControlMaster=auto");
+    assert_eq!(mux[5], "ControlPersist=10m");
+}
+
+#[test]
+fn profile_settings_override_global_settings() {
+    let config = load(
+        r#"
+[settings]
+control_persist = "30m"
+socket_dir = "/tmp/qs-test-sockets"
+server_alive_interval = 30
+connect_timeout = 5
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+
+[profiles.flaky]
+host = "10.0.0.6"
+user = "deploy"
+control_persist = "2h"
+server_alive_interval = 10
+server_alive_count_max = 6
+"#,
+    );
+
+    let web = config.get_profile("web").unwrap();
+    let web_settings = config.settings_for(web);
+    assert_eq!(web_settings.control_persist(), "30m");
+    assert_eq!(web_settings.server_alive_interval, Some(30));
+    assert_eq!(web_settings.server_alive_count_max, None);
+    assert_eq!(
+        setup_multiplex(&web_settings)[3],
+        "ControlPath=/tmp/qs-test-sockets/%C"
+    );
+
+    let flaky = config.get_profile("flaky").unwrap();
+    let flaky_settings = config.settings_for(flaky);
+    assert_eq!(flaky_settings.control_persist(), "2h");
+    assert_eq!(flaky_settings.connect_timeout, Some(5));
+    assert_eq!(
+        ssh_args(&config, flaky).unwrap(),
+        vec![
+            "-o",
+            "ServerAliveInterval=10",
+            "-o",
+            "ServerAliveCountMax=6",
+            "-o",
+            "ConnectTimeout=5",
+        ]
+    );
+}
+
+#[test]
+fn multiplexing_can_be_disabled_per_profile() {
+    let config = load(
+        r#"
+[settings]
+multiplex = true
+
+[profiles.legacy]
+host = "10.0.0.7"
+user = "root"
+port = 2222
+multiplex = false
+"#,
+    );
+
+    let legacy = config.get_profile("legacy").unwrap();
+    let settings = config.settings_for(legacy);
+    assert!(!settings.multiplex());
+    assert_eq!(
+        setup_multiplex(&settings),
+        vec!["-o", "ControlMaster=no", "-o", "ControlPath=none"]
+    );
+
+    assert_eq!(
+        rsync_ssh_command(&config, legacy).unwrap(),
+        "ssh -p 2222 -o ControlMaster=no -o ControlPath=none"
+    );
+}
+
+#[test]
+fn settings_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+
+    let config = load(
+        r#"
+default = "web"
+
+[settings]
+connect_timeout = 10
+
+[profiles.web]
+host = "10.0.0.5"
+user = "deploy"
+multiplex = false
+control_persist = "1h"
+"#,
+    );
+
+    config.save_to(config_path.clone()).unwrap();
+    let reloaded = Config::load_from(config_path).unwrap();
+
+    assert_eq!(reloaded.settings, config.settings);
+    assert_eq!(
+        reloaded.profiles.get("web").unwrap(),
+        config.profiles.get("web").unwrap()
+    );
+    assert_eq!(reloaded.profiles["web"].settings.multiplex, Some(false));
+}
+
+#[test]
+fn invalid_setting_type_is_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        "[settings]\nserver_alive_interval = \"often\"\n",
+    )
+    .unwrap();
+
+    let result = Config::load_from(config_path);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid config file format"));
+}
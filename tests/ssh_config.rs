@@ -0,0 +1,317 @@
+use qs::config::{Config, Profile};
+use qs::ssh_config::{
+    parse_ssh_config, plan_import, render_ssh_config, wildcard_match, ImportAction,
+};
+use std::collections::HashMap;
+use std::fs;
+use tempfile::TempDir;
+
+fn find<'a>(hosts: &'a [(String, Result<Profile, String>)], alias: &str) -> &'a Profile {
+    hosts
+        .iter()
+        .find(|(a, _)| a == alias)
+        .and_then(|(_, p)| p.as_ref().ok())
+        .unwrap()
+}
+
+#[test]
+fn wildcard_patterns() {
+    assert!(wildcard_match("*", "anything"));
+    assert!(wildcard_match("web-*", "web-01"));
+    assert!(wildcard_match("db?", "db1"));
+    assert!(wildcard_match("*.example.com", "a.b.example.com"));
+    assert!(!wildcard_match("db?", "db10"));
+    assert!(!wildcard_match("web-*", "api-01"));
+}
+
+#[test]
+fn parse_basic_host_blocks() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config");
+
+    fs::write(
+        &path,
+        r#"
+# Comment line
+Host web
+    HostName 10.0.0.5
+    User deploy
+    Port 2222
+
+Host db
+    HostName=10.0.0.6
+    User = admin
+    IdentityFile "/keys/db key"
+    ProxyJump web
+"#,
+    )
+    .unwrap();
+
+    let hosts = parse_ssh_config(&path).unwrap();
+    assert_eq!(hosts.len(), 2);
+
+    let web = find(&hosts, "web");
+    assert_eq!(web.host, "10.0.0.5");
+    assert_eq!(web.user, "deploy");
+    assert_eq!(web.port, 2222);
+    assert_eq!(web.jump, None);
+
+    let db = find(&hosts, "db");
+    assert_eq!(db.host, "10.0.0.6");
+    assert_eq!(db.user, "admin");
+    assert_eq!(db.port, 22);
+    assert_eq!(db.identity_file, Some("/keys/db key".to_string()));
+    assert_eq!(db.jump, Some("web".to_string()));
+}
+
+#[test]
+fn wildcard_blocks_are_inherited_first_value_wins() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config");
+
+    fs::write(
+        &path,
+        r#"
+User global
+
+Host prod-* !prod-legacy
+    User ops
+    ProxyJump bastion.example.com
+
+Host prod-api prod-legacy
+    HostName %h.internal
+
+Host *
+    User fallback
+    Port 2200
+"#,
+    )
+    .unwrap();
+
+    let hosts = parse_ssh_config(&path).unwrap();
+    let aliases: Vec<_> = hosts.iter().map(|(a, _)| a.as_str()).collect();
+    assert_eq!(aliases, vec!["prod-api", "prod-legacy"]);
+
+    // Options before the first Host apply to everything and come first
+    let api = find(&hosts, "prod-api");
+    assert_eq!(api.host, "prod-api.internal");
+    assert_eq!(api.user, "global");
+    assert_eq!(api.port, 2200);
+    assert_eq!(api.jump, Some("bastion.example.com".to_string()));
+
+    // Negated patterns exclude the host from the block
+    let legacy = find(&hosts, "prod-legacy");
+    assert_eq!(legacy.host, "prod-legacy.internal");
+    assert_eq!(legacy.jump, None);
+}
+
+#[test]
+fn include_directives_are_followed() {
+    let temp_dir = TempDir::new().unwrap();
+    let conf_d = temp_dir.path().join("conf.d");
+    fs::create_dir(&conf_d).unwrap();
+
+    fs::write(
+        conf_d.join("10-web.conf"),
+        "Host web\n    HostName 10.0.0.5\n    User deploy\n",
+    )
+    .unwrap();
+    fs::write(
+        conf_d.join("20-db.conf"),
+        "Host db\n    HostName 10.0.0.6\n    User admin\n",
+    )
+    .unwrap();
+    fs::write(conf_d.join("ignored.txt"), "Host nope\n").unwrap();
+
+    let path = temp_dir.path().join("config");
+    fs::write(
+        &path,
+        "Include conf.d/*.conf missing.conf\n\nHost *\n    Port 2022\n",
+    )
+    .unwrap();
+
+    let hosts = parse_ssh_config(&path).unwrap();
+    let aliases: Vec<_> = hosts.iter().map(|(a, _)| a.as_str()).collect();
+    assert_eq!(aliases, vec!["web", "db"]);
+
+    assert_eq!(find(&hosts, "web").port, 2022);
+    assert_eq!(find(&hosts, "db").user, "admin");
+}
+
+#[test]
+fn recursive_include_is_an_error() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config");
+    fs::write(&path, "Include config\n").unwrap();
+
+    let result = parse_ssh_config(&path);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Too many nested Include"));
+}
+
+#[test]
+fn invalid_port_is_reported_per_host() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config");
+    fs::write(
+        &path,
+        "Host bad\n    User x\n    Port abc\nHost good\n    User x\n",
+    )
+    .unwrap();
+
+    let hosts = parse_ssh_config(&path).unwrap();
+    assert_eq!(hosts[0].1, Err("Invalid port 'abc'".to_string()));
+    assert!(hosts[1].1.is_ok());
+}
+
+#[test]
+fn plan_import_reports_conflicts() {
+    let existing = Profile {
+        host: "10.0.0.5".to_string(),
+        user: "deploy".to_string(),
+        port: 22,
+        ..Default::default()
+    };
+
+    let mut profiles = HashMap::new();
+    profiles.insert("same".to_string(), existing.clone());
+    profiles.insert("changed".to_string(), existing.clone());
+
+    let mut groups = HashMap::new();
+    groups.insert("team".to_string(), vec!["same".to_string()]);
+
+    let config = Config {
+        default: Some("same".to_string()),
+        profiles,
+        groups,
+        ..Default::default()
+    };
+
+    let updated = Profile {
+        port: 2222,
+        ..existing.clone()
+    };
+    let hosts = vec![
+        ("new".to_string(), Ok(existing.clone())),
+        ("same".to_string(), Ok(existing.clone())),
+        ("changed".to_string(), Ok(updated.clone())),
+        ("team".to_string(), Ok(existing.clone())),
+        ("bad:alias".to_string(), Ok(existing.clone())),
+    ];
+
+    let actions: Vec<_> = plan_import(&config, hosts.clone(), false)
+        .into_iter()
+        .map(|entry| entry.action)
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ImportAction::Add,
+            ImportAction::Unchanged,
+            ImportAction::Conflict,
+            ImportAction::Invalid("'team' is already used as a group".to_string()),
+            ImportAction::Invalid("Alias cannot contain ':' character".to_string()),
+        ]
+    );
+
+    let plan = plan_import(&config, hosts, true);
+    assert_eq!(plan[2].action, ImportAction::Overwrite);
+    assert_eq!(plan[2].profile, Some(updated));
+}
+
+#[test]
+fn render_profiles_as_host_blocks() {
+    let mut profiles = HashMap::new();
+    profiles.insert(
+        "web".to_string(),
+        Profile {
+            host: "10.0.0.5".to_string(),
+            user: "deploy".to_string(),
+            port: 2222,
+            identity_file: Some("/keys/my key".to_string()),
+            ..Default::default()
+        },
+    );
+    profiles.insert(
+        "app".to_string(),
+        Profile {
+            host: "10.0.1.5".to_string(),
+            user: "ops".to_string(),
+            port: 22,
+            jump: Some("web".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let config = Config {
+        default: Some("web".to_string()),
+        profiles,
+        ..Default::default()
+    };
+
+    let rendered = render_ssh_config(&config).unwrap();
+    let blocks: Vec<_> = rendered.split("\nHost ").skip(1).collect();
+    assert_eq!(blocks.len(), 2);
+
+    // Blocks are sorted by alias
+    assert!(blocks[0].starts_with("app\n    HostName 10.0.1.5\n    User ops\n    ProxyJump web\n"));
+    assert!(!blocks[0].contains("Port"));
+
+    assert!(blocks[1].starts_with("web\n    HostName 10.0.0.5\n    User deploy\n    Port 2222\n"));
+    assert!(blocks[1].contains("    IdentityFile \"/keys/my key\"\n"));
+
+    for block in blocks {
+        assert!(block.contains("    ControlMaster auto\n"));
+        assert!(block.contains("    ControlPersist 10m\n"));
+        assert!(block.contains("/%C"));
+    }
+}
+
+#[test]
+fn rendered_config_round_trips_through_import() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("config");
+
+    let mut profiles = HashMap::new();
+    profiles.insert(
+        "db".to_string(),
+        Profile {
+            host: "10.0.0.6".to_string(),
+            user: "admin".to_string(),
+            port: 5022,
+            ..Default::default()
+        },
+    );
+    let config = Config {
+        profiles,
+        ..Default::default()
+    };
+
+    fs::write(&path, render_ssh_config(&config).unwrap()).unwrap();
+
+    let hosts = parse_ssh_config(&path).unwrap();
+    assert_eq!(find(&hosts, "db"), config.profiles.get("db").unwrap());
+}
+
+#[test]
+fn render_rejects_jump_cycles() {
+    let mut profiles = HashMap::new();
+    profiles.insert(
+        "loop".to_string(),
+        Profile {
+            host: "10.0.0.1".to_string(),
+            user: "x".to_string(),
+            port: 22,
+            jump: Some("loop".to_string()),
+            ..Default::default()
+        },
+    );
+    let config = Config {
+        profiles,
+        ..Default::default()
+    };
+
+    assert!(render_ssh_config(&config).is_err());
+}
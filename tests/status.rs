@@ -0,0 +1,35 @@
+mod common;
+
+use qs::config::Config;
+use std::fs;
+use std::os::unix::net::UnixListener;
+
+#[test]
+fn clean_only_removes_stale_sockets() {
+    // No master answers, so every socket is stale
+    let home = common::setup("exit 255");
+    let config_path = home.path().join(".config/qs/config.toml");
+    let mut config = Config::load_from(config_path.clone()).unwrap();
+    config.settings.socket_dir = Some("~/.ssh".to_string());
+    config.save_to(config_path).unwrap();
+
+    let ssh_dir = home.path().join(".ssh");
+    fs::create_dir(&ssh_dir).unwrap();
+    fs::write(ssh_dir.join("id_ed25519"), "private key").unwrap();
+    fs::write(ssh_dir.join("known_hosts"), "host key").unwrap();
+    drop(UnixListener::bind(ssh_dir.join("stale-master")).unwrap());
+
+    let output = common::qs(&home, &["status", "--all", "--clean"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("stale-master  ✗ stale"), "{stdout}");
+    assert!(!stdout.contains("id_ed25519"), "{stdout}");
+    assert!(stdout.contains("✓ Removed 1 stale socket(s)"), "{stdout}");
+
+    assert!(!ssh_dir.join("stale-master").exists());
+    assert_eq!(
+        fs::read_to_string(ssh_dir.join("id_ed25519")).unwrap(),
+        "private key"
+    );
+    assert!(ssh_dir.join("known_hosts").exists());
+}
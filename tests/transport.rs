@@ -0,0 +1,786 @@
+use qs::config::{
+    Config, ConnectionSettings, Forward, ForwardKind, Multiplexer, Profile, Transfer,
+};
+use qs::error::Error;
+use qs::keys::{
+    install_key, master_command, remove_key_script, revoke_key, InstallOutcome, SSHD_CONFIG,
+};
+use qs::session::{MasterStatus, Session, SyncOptions, TransferOptions};
+use qs::transport::Recorder;
+use qs::util::{
+    capture_on_hosts, close_socket, control_path, exec_on_hosts, forward_port, socket_pid,
+    start_master,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+struct Fixture {
+    session: Session,
+    recorder: Arc<Recorder>,
+    sockets: TempDir,
+}
+
+fn setup(recorder: Recorder) -> Fixture {
+    setup_with(recorder, |_| {})
+}
+
+fn setup_with(recorder: Recorder, customize: impl FnOnce(&mut Profile)) -> Fixture {
+    let sockets = TempDir::new().unwrap();
+    let mut profile = Profile {
+        host: "10.0.0.5".to_string(),
+        user: "deploy".to_string(),
+        port: 2222,
+        settings: ConnectionSettings {
+            socket_dir: Some(sockets.path().display().to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    customize(&mut profile);
+
+    let recorder = Arc::new(recorder);
+    let session = Session::new("web", profile).transport(recorder.clone());
+
+    Fixture {
+        session,
+        recorder,
+        sockets,
+    }
+}
+
+impl Fixture {
+    fn transfer(self, method: Transfer) -> Self {
+        Self {
+            session: self.session.transfer(method),
+            ..self
+        }
+    }
+
+    fn options(self, options: TransferOptions) -> Self {
+        Self {
+            session: self.session.options(options),
+            ..self
+        }
+    }
+
+    fn multiplex(&self) -> Vec<String> {
+        [
+            "-o",
+            "ControlMaster=auto",
+            "-o",
+            &format!("ControlPath={}/%C", self.sockets.path().display()),
+            "-o",
+            "ControlPersist=10m",
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+
+    /// A config with the session's host as `web`, and a copy of it at
+    /// 10.0.0.6 as `db`.
+    fn config(&self) -> Config {
+        let web = self.session.profile().clone();
+        let db = Profile {
+            host: "10.0.0.6".to_string(),
+            ..web.clone()
+        };
+
+        let mut config = Config::default();
+        config.profiles.insert("web".to_string(), web);
+        config.profiles.insert("db".to_string(), db);
+        config
+    }
+
+    fn ssh(&self, tail: &[&str]) -> Vec<String> {
+        let mut argv = vec!["ssh".to_string()];
+        argv.extend(self.multiplex());
+        argv.extend(["-p", "2222"].map(str::to_string));
+        argv.extend(tail.iter().map(|arg| arg.to_string()));
+        argv
+    }
+
+    fn rsync(&self, source: &str, dest: &str) -> Vec<String> {
+        let ssh = format!("ssh -p 2222 {}", self.multiplex().join(" "));
+        ["rsync", "-az", "-e", &ssh, source, dest]
+            .map(str::to_string)
+            .to_vec()
+    }
+}
+
+#[test]
+fn connect_opens_a_shell() {
+    let fixture = setup(Recorder::new());
+    assert!(fixture.session.run(None).unwrap().success());
+
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&["deploy@10.0.0.5"])]
+    );
+}
+
+#[test]
+fn tty_is_forced_on_or_off() {
+    let fixture = setup(Recorder::new());
+    let expected = [
+        fixture.ssh(&["-tt", "deploy@10.0.0.5", "htop"]),
+        fixture.ssh(&["-T", "deploy@10.0.0.5", "pg_dump app"]),
+    ];
+
+    let session = fixture.session.tty(Some(true));
+    session.run(Some("htop")).unwrap();
+    let session = session.tty(Some(false));
+    session.run(Some("pg_dump app")).unwrap();
+
+    assert_eq!(fixture.recorder.calls(), expected);
+}
+
+#[test]
+fn connect_ahead_of_time_runs_true() {
+    let fixture = setup(Recorder::new());
+    fixture.session.connect().unwrap();
+
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&["deploy@10.0.0.5", "true"])]
+    );
+}
+
+#[test]
+fn exec_passes_the_command_and_captures_output() {
+    let fixture = setup(Recorder::new().exit_code(3).stdout("up 2 days\n"));
+    let output = fixture.session.exec("uptime").unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"up 2 days\n");
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&["deploy@10.0.0.5", "uptime"])]
+    );
+}
+
+#[test]
+fn send_and_get_put_the_remote_path_after_the_target() {
+    let fixture = setup(Recorder::new());
+    fixture
+        .session
+        .send(Path::new("/tmp/app.tar"), "/srv/app/")
+        .unwrap();
+    fixture
+        .session
+        .get("/var/log/app.log", Path::new("/tmp/logs"))
+        .unwrap();
+
+    let probe = fixture.ssh(&["deploy@10.0.0.5", "command -v rsync"]);
+    assert_eq!(
+        fixture.recorder.calls(),
+        [
+            probe.clone(),
+            fixture.rsync("/tmp/app.tar", "deploy@10.0.0.5:/srv/app/"),
+            probe,
+            fixture.rsync("deploy@10.0.0.5:/var/log/app.log", "/tmp/logs"),
+        ]
+    );
+}
+
+#[test]
+fn transfers_fall_back_to_scp_without_local_rsync() {
+    let fixture = setup(Recorder::new().missing("rsync"));
+    fixture
+        .session
+        .send(Path::new("/tmp/app.tar"), "/srv/app/")
+        .unwrap();
+
+    assert_eq!(
+        fixture.recorder.calls(),
+        [[
+            vec![
+                "scp".to_string(),
+                "-r".to_string(),
+                "-p".to_string(),
+                "-q".to_string()
+            ],
+            fixture.multiplex(),
+            ["-P", "2222", "/tmp/app.tar", "deploy@10.0.0.5:/srv/app/"]
+                .map(str::to_string)
+                .to_vec(),
+        ]
+        .concat()]
+    );
+}
+
+#[test]
+fn transfers_fall_back_when_the_host_lacks_rsync() {
+    let fixture = setup(Recorder::new().exit_code(1));
+    let result = fixture
+        .session
+        .get("/var/log/app.log", Path::new("/tmp/logs"));
+
+    assert_eq!(result, Err(Error::Transfer { code: 1 }));
+    let calls = fixture.recorder.calls();
+    assert_eq!(
+        calls[0],
+        fixture.ssh(&["deploy@10.0.0.5", "command -v rsync"])
+    );
+    assert_eq!(calls[1][0], "scp");
+    assert_eq!(
+        calls[1][calls[1].len() - 2..],
+        ["deploy@10.0.0.5:/var/log/app.log", "/tmp/logs"]
+    );
+}
+
+#[test]
+fn sftp_is_used_when_forced_or_scp_is_missing() {
+    for fixture in [
+        setup(Recorder::new().missing("rsync").missing("scp")),
+        setup(Recorder::new()).transfer(Transfer::Sftp),
+    ] {
+        fixture
+            .session
+            .send(Path::new("/tmp/app.tar"), "~/app/")
+            .unwrap();
+
+        let calls = fixture.recorder.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][..2], ["sftp", "-b"]);
+        assert!(calls[0][2].contains("qs-sftp-"));
+        // The batch file is cleaned up afterwards
+        assert!(!Path::new(&calls[0][2]).exists());
+        assert_eq!(calls[0][3..calls[0].len() - 3], fixture.multiplex());
+        assert_eq!(
+            calls[0][calls[0].len() - 3..],
+            ["-P", "2222", "deploy@10.0.0.5"]
+        );
+    }
+}
+
+#[test]
+fn failed_transfers_report_rsync_code() {
+    let fixture = setup(Recorder::new().exit_code(23)).transfer(Transfer::Rsync);
+    let result = fixture.session.send(Path::new("/tmp/a"), "/tmp/");
+
+    assert_eq!(result, Err(Error::Transfer { code: 23 }));
+}
+
+#[test]
+fn status_checks_the_master() {
+    let fixture = setup(Recorder::new().stderr("Master running (pid=4242)\r\n"));
+
+    assert_eq!(
+        fixture.session.status().unwrap(),
+        MasterStatus::Active(4242)
+    );
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&["-O", "check", "deploy@10.0.0.5"])]
+    );
+
+    let fixture = setup(Recorder::new().exit_code(255));
+    assert_eq!(fixture.session.status().unwrap(), MasterStatus::Inactive);
+}
+
+#[test]
+fn close_exits_the_master() {
+    let fixture = setup(Recorder::new());
+    assert!(fixture.session.close().unwrap());
+
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&["-O", "exit", "deploy@10.0.0.5"])]
+    );
+}
+
+#[test]
+fn jump_hosts_come_from_the_config() {
+    let mut config = Config::default();
+    for (alias, host, jump) in [
+        ("bastion", "10.0.0.1", None),
+        ("app", "10.0.1.5", Some("bastion")),
+    ] {
+        let profile = Profile {
+            host: host.to_string(),
+            user: "admin".to_string(),
+            jump: jump.map(str::to_string),
+            settings: ConnectionSettings {
+                multiplex: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.profiles.insert(alias.to_string(), profile);
+    }
+
+    let recorder = Arc::new(Recorder::new());
+    let session = Session::from_config(&config, "app")
+        .unwrap()
+        .transport(recorder.clone());
+    session.exec("hostname").unwrap();
+
+    assert_eq!(
+        recorder.calls(),
+        [[
+            "ssh",
+            "-o",
+            "ControlMaster=no",
+            "-o",
+            "ControlPath=none",
+            "-J",
+            "admin@10.0.0.1:22",
+            "admin@10.0.1.5",
+            "hostname",
+        ]]
+    );
+}
+
+#[test]
+fn sync_mirrors_the_directory_contents() {
+    let fixture = setup(Recorder::new()).transfer(Transfer::Rsync);
+    let options = SyncOptions {
+        delete: true,
+        exclude: vec!["*.log".to_string(), "target/".to_string()],
+        exclude_from: vec![PathBuf::from(".syncignore")],
+        gitignore: true,
+        dry_run: true,
+        checksum: true,
+    };
+    fixture
+        .session
+        .sync(Path::new("/home/me/project"), "~/project", &options)
+        .unwrap();
+
+    let mut expected = fixture.rsync("", "");
+    expected.truncate(4);
+    expected.extend(
+        [
+            "--delete",
+            "--checksum",
+            "--dry-run",
+            "--itemize-changes",
+            "--exclude=.git",
+            "--exclude=*.log",
+            "--exclude=target/",
+            "--exclude-from=.syncignore",
+            "--filter=:- .gitignore",
+            "/home/me/project/",
+            "deploy@10.0.0.5:~/project",
+        ]
+        .map(str::to_string),
+    );
+    assert_eq!(fixture.recorder.calls(), [expected]);
+}
+
+#[test]
+fn sync_leaves_out_git_directories() {
+    let fixture = setup(Recorder::new()).transfer(Transfer::Rsync);
+    fixture
+        .session
+        .sync_paths(
+            Path::new("/home/me/project"),
+            "~/project",
+            &[PathBuf::from("src/main.rs")],
+            &SyncOptions::default(),
+        )
+        .unwrap();
+
+    let calls = fixture.recorder.calls();
+    assert!(calls[0].contains(&"--exclude=.git".to_string()));
+}
+
+#[test]
+fn sync_needs_rsync() {
+    let fixture = setup(Recorder::new().missing("rsync"));
+    let result = fixture.session.sync(
+        Path::new("/home/me/project/"),
+        "/srv/project",
+        &SyncOptions::default(),
+    );
+
+    assert_eq!(result, Err(Error::Dependency(vec!["rsync".to_string()])));
+    assert!(fixture.recorder.calls().is_empty());
+}
+
+#[test]
+fn sync_paths_only_sends_the_listed_paths() {
+    for (delete, missing) in [
+        (false, "--ignore-missing-args"),
+        (true, "--delete-missing-args"),
+    ] {
+        let fixture = setup(Recorder::new()).transfer(Transfer::Rsync);
+        let options = SyncOptions {
+            delete,
+            ..Default::default()
+        };
+        fixture
+            .session
+            .sync_paths(
+                Path::new("/home/me/project"),
+                "~/project",
+                &[PathBuf::from("src/main.rs"), PathBuf::from("docs")],
+                &options,
+            )
+            .unwrap();
+
+        let calls = fixture.recorder.calls();
+        let argv = &calls[0];
+        assert_eq!(argv[..4], fixture.rsync("", "")[..4]);
+        let tail = &argv[argv.len() - 6..];
+        assert_eq!(tail[..2], ["-r", "--from0"]);
+        assert!(tail[2].starts_with("--files-from="));
+        // The list is cleaned up afterwards
+        assert!(!Path::new(&tail[2]["--files-from=".len()..]).exists());
+        assert_eq!(
+            tail[3..],
+            [missing, "/home/me/project/", "deploy@10.0.0.5:~/project"]
+        );
+    }
+}
+
+fn transfer_options() -> TransferOptions {
+    TransferOptions {
+        bwlimit: Some(5000),
+        partial: true,
+        preserve_perms: true,
+        compress_level: Some(3),
+        rsync_args: Vec::new(),
+    }
+}
+
+#[test]
+fn rsync_flags_come_after_the_profile_defaults() {
+    let fixture = setup_with(Recorder::new(), |profile| {
+        profile.rsync_args = vec!["--no-perms".to_string(), "--bwlimit=100".to_string()];
+    })
+    .transfer(Transfer::Rsync)
+    .options(TransferOptions {
+        rsync_args: vec!["--info=progress2".to_string()],
+        ..transfer_options()
+    });
+    fixture
+        .session
+        .send(Path::new("/tmp/app.tar"), "/srv/")
+        .unwrap();
+
+    let mut expected = fixture.rsync("", "");
+    expected.truncate(4);
+    expected.extend(
+        [
+            "--no-perms",
+            "--bwlimit=100",
+            "--bwlimit=5000",
+            "--partial",
+            "--perms",
+            "--compress-level=3",
+            "--info=progress2",
+            "/tmp/app.tar",
+            "deploy@10.0.0.5:/srv/",
+        ]
+        .map(str::to_string),
+    );
+    assert_eq!(fixture.recorder.calls(), [expected]);
+}
+
+#[test]
+fn scp_and_sftp_get_the_bandwidth_limit_in_kbits() {
+    for method in [Transfer::Scp, Transfer::Sftp] {
+        let fixture = setup_with(Recorder::new(), |profile| {
+            profile.rsync_args = vec!["--partial-dir=.part".to_string()];
+        })
+        .transfer(method)
+        .options(transfer_options());
+        fixture
+            .session
+            .send(Path::new("/tmp/app.tar"), "/srv/")
+            .unwrap();
+
+        let calls = fixture.recorder.calls();
+        assert!(calls[0].windows(2).any(|pair| pair == ["-l", "40000"]));
+        assert!(!calls[0].iter().any(|arg| arg.starts_with("--")));
+    }
+}
+
+#[test]
+fn rsync_flags_need_rsync() {
+    let fixture = setup(Recorder::new())
+        .transfer(Transfer::Scp)
+        .options(TransferOptions {
+            rsync_args: vec!["--inplace".to_string()],
+            ..Default::default()
+        });
+    let result = fixture.session.send(Path::new("/tmp/app.tar"), "/srv/");
+
+    assert_eq!(result, Err(Error::Dependency(vec!["rsync".to_string()])));
+    assert!(fixture.recorder.calls().is_empty());
+}
+
+#[test]
+fn workdir_and_env_apply_to_shells_and_commands() {
+    let fixture = setup_with(Recorder::new(), |profile| {
+        profile.workdir = Some("/srv/app".to_string());
+        profile.env.insert("STAGE".to_string(), "prod".to_string());
+    });
+    fixture.session.run(None).unwrap();
+    fixture.session.exec("make test").unwrap();
+
+    let prelude = "cd '/srv/app' || exit 1; export STAGE='prod'; ";
+    assert_eq!(
+        fixture.recorder.calls(),
+        [
+            fixture.ssh(&[
+                "-tt",
+                "deploy@10.0.0.5",
+                &format!(r#"{prelude}exec "${{SHELL:-sh}}" -l"#),
+            ]),
+            fixture.ssh(&["deploy@10.0.0.5", &format!("{prelude}make test")]),
+        ]
+    );
+}
+
+#[test]
+fn workdir_is_left_out_of_internal_commands() {
+    let fixture = setup_with(Recorder::new(), |profile| {
+        profile.workdir = Some("/srv/app".to_string());
+    });
+    fixture.session.connect().unwrap();
+    fixture
+        .session
+        .send(Path::new("/tmp/app.tar"), "/srv/")
+        .unwrap();
+
+    let calls = fixture.recorder.calls();
+    assert_eq!(calls[0], fixture.ssh(&["deploy@10.0.0.5", "true"]));
+    assert_eq!(
+        calls[1],
+        fixture.ssh(&["deploy@10.0.0.5", "command -v rsync"])
+    );
+}
+
+#[test]
+fn attach_forces_a_terminal_and_starts_in_workdir() {
+    let fixture = setup_with(Recorder::new(), |profile| {
+        profile.workdir = Some("/srv/app".to_string());
+        profile.session = Some(Multiplexer::Tmux);
+    });
+    fixture.session.attach("work").unwrap();
+
+    assert_eq!(
+        fixture.recorder.calls(),
+        [fixture.ssh(&[
+            "-tt",
+            "deploy@10.0.0.5",
+            "cd '/srv/app' || exit 1; tmux new-session -A -s work",
+        ])]
+    );
+}
+
+#[test]
+fn sessions_asks_both_multiplexers_unless_the_profile_picks_one() {
+    let fixture = setup(Recorder::new().exit_code(1));
+    assert!(fixture.session.sessions().unwrap().is_empty());
+    let calls = fixture.recorder.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[0].last().unwrap().starts_with("tmux list-sessions"));
+    assert!(calls[1].last().unwrap().starts_with("screen -ls"));
+
+    let fixture = setup_with(Recorder::new().stdout("1 qs\n"), |profile| {
+        profile.session = Some(Multiplexer::Tmux);
+        profile.workdir = Some("/srv/app".to_string());
+    });
+    let sessions = fixture.session.sessions().unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].name, "qs");
+    assert!(sessions[0].attached);
+    // Listing doesn't depend on the workdir existing
+    assert!(fixture.recorder.calls()[0]
+        .last()
+        .unwrap()
+        .starts_with("tmux "));
+}
+
+#[test]
+fn sessions_reports_connection_failures() {
+    let fixture = setup(
+        Recorder::new()
+            .exit_code(255)
+            .stderr("Connection refused\n"),
+    );
+    assert_eq!(
+        fixture.session.sessions(),
+        Err(Error::Transport("Connection refused".to_string()))
+    );
+}
+
+fn aliases() -> Vec<String> {
+    vec!["web".to_string(), "db".to_string()]
+}
+
+#[test]
+fn group_exec_runs_on_every_host_in_batch_mode() {
+    let fixture = setup(Recorder::new().exit_code(3));
+    let config = fixture.config();
+
+    let results = exec_on_hosts(&config, &*fixture.recorder, &aliases(), "uptime", None);
+    assert_eq!(
+        results,
+        [("web".to_string(), Some(3)), ("db".to_string(), Some(3))]
+    );
+
+    // Hosts run at once, in no particular order
+    let mut calls = fixture.recorder.calls();
+    calls.sort();
+    let expected: Vec<_> = ["deploy@10.0.0.5", "deploy@10.0.0.6"]
+        .map(|target| {
+            let mut argv = vec!["ssh".to_string()];
+            argv.extend(fixture.multiplex());
+            argv.extend(
+                ["-o", "BatchMode=yes", "-p", "2222", target, "uptime"].map(str::to_string),
+            );
+            argv
+        })
+        .to_vec();
+    assert_eq!(calls, expected);
+}
+
+#[test]
+fn group_exec_captures_output_per_host() {
+    let fixture = setup(Recorder::new().stdout("up 2 days\n").stderr("warn\n"));
+    let config = fixture.config();
+
+    let records = capture_on_hosts(&config, &*fixture.recorder, &aliases(), "uptime", None);
+    assert_eq!(records.len(), 2);
+    for (record, alias) in records.iter().zip(["web", "db"]) {
+        assert_eq!(record.alias, alias);
+        assert_eq!(record.stdout, "up 2 days\n");
+        assert_eq!(record.stderr, "warn\n");
+        assert_eq!(record.exit_code, Some(0));
+    }
+    assert_eq!(fixture.recorder.calls().len(), 2);
+}
+
+#[test]
+fn forwards_go_through_a_lasting_master() {
+    let fixture = setup(Recorder::new());
+    let config = fixture.config();
+    let profile = &config.profiles["web"];
+    let forward = Forward {
+        kind: ForwardKind::Local,
+        spec: "5432:localhost:5432".to_string(),
+    };
+
+    start_master(&config, &*fixture.recorder, profile).unwrap();
+    forward_port(&config, &*fixture.recorder, profile, &forward, false).unwrap();
+    forward_port(&config, &*fixture.recorder, profile, &forward, true).unwrap();
+
+    let mut master = ["ssh", "-M", "-N", "-f", "-o", "ControlPersist=no"]
+        .map(str::to_string)
+        .to_vec();
+    master.extend(fixture.ssh(&["deploy@10.0.0.5"]).into_iter().skip(1));
+    assert_eq!(
+        fixture.recorder.calls(),
+        [
+            master,
+            fixture.ssh(&[
+                "-O",
+                "forward",
+                "-L",
+                "5432:localhost:5432",
+                "deploy@10.0.0.5"
+            ]),
+            fixture.ssh(&[
+                "-O",
+                "cancel",
+                "-L",
+                "5432:localhost:5432",
+                "deploy@10.0.0.5"
+            ]),
+        ]
+    );
+}
+
+#[test]
+fn forward_failures_report_what_ssh_said() {
+    let fixture = setup(
+        Recorder::new()
+            .exit_code(255)
+            .stderr("mux_client_forward: forwarding request failed: Port forwarding failed\n"),
+    );
+    let config = fixture.config();
+    let forward = Forward {
+        kind: ForwardKind::Remote,
+        spec: "8080:localhost:80".to_string(),
+    };
+
+    assert_eq!(
+        forward_port(
+            &config,
+            &*fixture.recorder,
+            &config.profiles["web"],
+            &forward,
+            false
+        ),
+        Err(Error::Transport(
+            "mux_client_forward: forwarding request failed: Port forwarding failed".to_string()
+        ))
+    );
+}
+
+#[test]
+fn sockets_are_checked_and_closed_by_path() {
+    let fixture = setup(Recorder::new().stderr("Master running (pid=4242)\r\n"));
+    let socket = fixture.sockets.path().join("abc123");
+
+    assert_eq!(socket_pid(&*fixture.recorder, &socket), Some(4242));
+    assert!(close_socket(&*fixture.recorder, &socket));
+
+    let control_path = format!("ControlPath={}", socket.display());
+    assert_eq!(
+        fixture.recorder.calls(),
+        [
+            ["ssh", "-o", &control_path, "-O", "check", "qs"],
+            ["ssh", "-o", &control_path, "-O", "exit", "qs"],
+        ]
+    );
+}
+
+#[test]
+fn control_path_asks_ssh() {
+    let fixture = setup(Recorder::new().stdout("user deploy\ncontrolpath /tmp/s/abc\nport 2222\n"));
+    let config = fixture.config();
+
+    assert_eq!(
+        control_path(&config, &*fixture.recorder, &config.profiles["web"]).unwrap(),
+        Some(PathBuf::from("/tmp/s/abc"))
+    );
+
+    let mut expected = fixture.ssh(&["deploy@10.0.0.5"]);
+    expected.insert(1, "-G".to_string());
+    assert_eq!(fixture.recorder.calls(), [expected]);
+}
+
+#[test]
+fn keys_are_installed_and_revoked_over_the_master() {
+    let fixture = setup(Recorder::new().stdout("removed\n"));
+    let config = fixture.config();
+    let profile = &config.profiles["web"];
+    let key = fixture.sockets.path().join("id_ed25519");
+    fs::write(key.with_extension("pub"), "ssh-ed25519 AAAAkey qs-tool\n").unwrap();
+
+    assert_eq!(
+        revoke_key(&config, &*fixture.recorder, profile, &key),
+        Ok(true)
+    );
+    let mut expected = fixture.ssh(&["deploy@10.0.0.5"]);
+    expected.push(remove_key_script(
+        "ssh-ed25519 AAAAkey qs-tool",
+        SSHD_CONFIG,
+    ));
+    assert_eq!(fixture.recorder.calls(), [expected]);
+
+    let fixture = setup(Recorder::new().stdout("added\n"));
+    let cmd = master_command(&config, profile, true).unwrap();
+    assert_eq!(
+        install_key(&*fixture.recorder, cmd, "ssh-ed25519 AAAAkey qs-tool"),
+        Ok(InstallOutcome::Added)
+    );
+    assert!(fixture.recorder.calls()[0].contains(&"BatchMode=yes".to_string()));
+}
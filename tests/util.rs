@@ -1,4 +1,12 @@
-use qs::util::check_command;
+use qs::config::Profile;
+use qs::error::Error;
+use qs::transport::Process;
+use qs::util::{
+    check_command, parse_master_pid, sftp_batch, socket_files, socket_pid, split_remote,
+    with_workdir,
+};
+use std::os::unix::net::UnixListener;
+use tempfile::TempDir;
 
 #[test]
 fn check_command_returns_false_for_nonexistent() {
@@ -12,3 +20,119 @@ fn check_command_returns_true_for_common_tools() {
         assert!(check_command("ls"));
     }
 }
+
+#[test]
+fn parse_master_pid_from_check_output() {
+    assert_eq!(
+        parse_master_pid("Master running (pid=12345)\r\n"),
+        Some(12345)
+    );
+    assert_eq!(
+        parse_master_pid("Control socket connect(/x): No such file"),
+        None
+    );
+    assert_eq!(parse_master_pid("pid="), None);
+}
+
+#[test]
+fn socket_pid_is_none_for_stale_socket() {
+    if !check_command("ssh") {
+        return;
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("stale");
+    std::fs::write(&socket, "").unwrap();
+
+    assert_eq!(socket_pid(&Process, &socket), None);
+}
+
+#[test]
+fn socket_files_only_lists_sockets() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    std::fs::write(dir.join("id_ed25519"), "key").unwrap();
+    std::fs::create_dir(dir.join("sockets")).unwrap();
+    let _listener = UnixListener::bind(dir.join("master")).unwrap();
+
+    assert_eq!(socket_files(dir), vec![dir.join("master")]);
+}
+
+#[test]
+fn sftp_batch_quotes_paths_and_drops_tilde() {
+    assert_eq!(
+        sftp_batch("/tmp/my \"file\"", "~/in box/", true, false, false),
+        "put -rp \"/tmp/my \\\"file\\\"\" \"in box/\"\n"
+    );
+    assert_eq!(
+        sftp_batch("/tmp/logs", "~", false, true, false),
+        "progress\nget -rp \".\" \"/tmp/logs\"\n"
+    );
+    assert_eq!(
+        sftp_batch("app.tar", "/srv/", true, false, true),
+        "put -rpa \"app.tar\" \"/srv/\"\n"
+    );
+}
+
+#[test]
+fn split_remote_defaults_the_alias() {
+    assert_eq!(split_remote("web:/srv/app"), ("web", "/srv/app"));
+    assert_eq!(split_remote("/srv/app"), ("default", "/srv/app"));
+    assert_eq!(split_remote("web:C:/odd"), ("web", "C:/odd"));
+}
+
+#[test]
+fn with_workdir_leaves_plain_profiles_alone() {
+    let profile = Profile::default();
+    assert_eq!(
+        with_workdir(&profile, Some("uptime")).unwrap().as_deref(),
+        Some("uptime")
+    );
+    assert_eq!(with_workdir(&profile, None).unwrap(), None);
+}
+
+#[test]
+fn with_workdir_changes_directory_and_exports_env() {
+    let mut profile = Profile {
+        workdir: Some("/srv/my app".to_string()),
+        ..Default::default()
+    };
+    profile.env.insert("STAGE".to_string(), "prod".to_string());
+    profile
+        .env
+        .insert("GREETING".to_string(), "it's up".to_string());
+
+    assert_eq!(
+        with_workdir(&profile, Some("ls -la")).unwrap().unwrap(),
+        r"cd '/srv/my app' || exit 1; export GREETING='it'\''s up' STAGE='prod'; ls -la"
+    );
+    assert_eq!(
+        with_workdir(&profile, None).unwrap().unwrap(),
+        r#"cd '/srv/my app' || exit 1; export GREETING='it'\''s up' STAGE='prod'; exec "${SHELL:-sh}" -l"#
+    );
+}
+
+#[test]
+fn with_workdir_expands_home() {
+    for (workdir, cd) in [("~", "cd ~"), ("~/app dir", "cd ~/'app dir'")] {
+        let profile = Profile {
+            workdir: Some(workdir.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            with_workdir(&profile, Some("pwd")).unwrap().unwrap(),
+            format!("{cd} || exit 1; pwd")
+        );
+    }
+}
+
+#[test]
+fn with_workdir_rejects_bad_variable_names() {
+    let mut profile = Profile::default();
+    profile.env.insert("BAD-NAME".to_string(), "x".to_string());
+
+    assert!(matches!(
+        with_workdir(&profile, Some("true")),
+        Err(Error::Config(_))
+    ));
+}
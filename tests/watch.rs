@@ -0,0 +1,56 @@
+use qs::watch::Changes;
+use std::path::{Path, PathBuf};
+
+#[test]
+fn changes_are_relative_to_the_watched_directory() {
+    let mut changes = Changes::new(Path::new("/home/me/project"));
+    changes.add(Path::new("/home/me/project/src/main.rs"));
+    changes.add(Path::new("/home/me/project/src/main.rs"));
+    changes.add(Path::new("/home/me/project/README.md"));
+
+    assert_eq!(
+        changes.take(),
+        [PathBuf::from("README.md"), PathBuf::from("src/main.rs")]
+    );
+    assert!(changes.is_empty());
+}
+
+#[test]
+fn changes_skip_the_root_outside_paths_and_git() {
+    let mut changes = Changes::new(Path::new("/home/me/project"));
+    changes.add(Path::new("/home/me/project"));
+    changes.add(Path::new("/home/me/other/file"));
+    changes.add(Path::new("/home/me/project/.git/index"));
+    changes.add(Path::new("/home/me/project/vendor/lib/.git"));
+
+    assert!(changes.is_empty());
+}
+
+#[test]
+fn changed_directories_cover_their_contents() {
+    let mut changes = Changes::new(Path::new("/p"));
+    for path in [
+        "/p/build/out/a.o",
+        "/p/build",
+        "/p/build/b.o",
+        "/p/buildlog",
+    ] {
+        changes.add(Path::new(path));
+    }
+
+    assert_eq!(
+        changes.take(),
+        [PathBuf::from("build"), PathBuf::from("buildlog")]
+    );
+}
+
+#[test]
+fn restored_changes_are_pushed_again() {
+    let mut changes = Changes::new(Path::new("/p"));
+    changes.add(Path::new("/p/a"));
+    let paths = changes.take();
+    changes.add(Path::new("/p/b"));
+    changes.restore(paths);
+
+    assert_eq!(changes.take(), [PathBuf::from("a"), PathBuf::from("b")]);
+}